@@ -0,0 +1,45 @@
+use std::{process, time::Duration};
+
+use openglm::mock_server::{MockReply, MockServer};
+
+const USAGE: &str = "usage: openglm-mock [--addr 127.0.0.1:8080] [--api-key id.secret]... [--latency-ms N] [--chunk-latency-ms N] [--echo] [--script replies.json]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut server = MockServer::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("missing value for {}", arg)));
+        match arg.as_str() {
+            "--addr" => addr = value(),
+            "--api-key" => server = server.with_api_key(value()),
+            "--latency-ms" => server = server.with_latency(Duration::from_millis(value().parse().unwrap_or_else(|_| fail("invalid --latency-ms")))),
+            "--chunk-latency-ms" => server = server.with_chunk_latency(Duration::from_millis(value().parse().unwrap_or_else(|_| fail("invalid --chunk-latency-ms")))),
+            "--echo" => server = server.with_echo(true),
+            "--script" => {
+                let path = value();
+                let content = std::fs::read(&path).unwrap_or_else(|e| fail(&format!("failed to read {}: {}", path, e)));
+                let replies: Vec<MockReply> = serde_json::from_slice(&content).unwrap_or_else(|e| fail(&format!("invalid script {}: {}", path, e)));
+                for reply in replies {
+                    server = server.with_reply(reply);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => fail(&format!("unknown argument {}", arg)),
+        }
+    }
+
+    let handle = server.bind(&addr).await.unwrap_or_else(|e| fail(&format!("failed to bind {}: {}", addr, e)));
+    eprintln!("openglm-mock listening on {}", handle.base_url());
+    handle.wait().await;
+}
@@ -0,0 +1,55 @@
+use std::process;
+
+use openglm::prelude::*;
+use openglm::proxy::Proxy;
+
+const USAGE: &str = "usage: openglm-proxy [--addr 127.0.0.1:8000] [--api-key id.secret] [--access-key KEY]... [--base-url URL]
+
+GLM密钥未通过--api-key指定时从OPENGLM_API_KEY环境变量读取。
+设置--access-key后，调用方需要携带其中之一作为Bearer token。";
+
+const API_KEY_ENV: &str = "OPENGLM_API_KEY";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut addr = "127.0.0.1:8000".to_string();
+    let mut api_key = std::env::var(API_KEY_ENV).ok();
+    let mut base_url = None;
+    let mut access_keys = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("missing value for {}", arg)));
+        match arg.as_str() {
+            "--addr" => addr = value(),
+            "--api-key" => api_key = Some(value()),
+            "--access-key" => access_keys.push(value()),
+            "--base-url" => base_url = Some(value()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => fail(&format!("unknown argument {}", arg)),
+        }
+    }
+
+    let api_key = api_key.filter(|api_key| !api_key.is_empty()).unwrap_or_else(|| fail("no api key"));
+    let mut client = OpenGLM::new(api_key);
+    if let Some(base_url) = base_url {
+        client = client.with_base_url(base_url);
+    }
+
+    let mut proxy = Proxy::new(client);
+    for access_key in access_keys {
+        proxy = proxy.with_access_key(access_key);
+    }
+
+    let handle = proxy.bind(&addr).await.unwrap_or_else(|e| fail(&format!("failed to bind {}: {}", addr, e)));
+    eprintln!("openglm-proxy listening on {}", handle.base_url());
+    handle.wait().await;
+}
@@ -0,0 +1,331 @@
+use std::{io::{self, BufRead, IsTerminal, Read, Write}, path::PathBuf, process};
+
+use openglm::chat::completions::completions::CompletionsRequestBuilder;
+use openglm::prelude::*;
+
+const USAGE: &str = "usage: openglm [options] [prompt...]
+
+不带prompt且标准输入为终端时进入交互模式，否则发送一次后退出。
+
+options:
+  -m, --model MODEL        模型名称，默认glm-4-flash
+  -t, --temperature F      采样温度
+  -s, --system TEXT        系统提示词
+      --json               输出接口返回的原始JSON
+      --no-stream          等待完整回复后再输出
+      --load FILE          从记录文件加载对话，不会写回该文件，需要时另外指定--save
+      --save FILE          每轮结束后将对话保存到记录文件
+      --base-url URL       接口地址
+      --config FILE        配置文件，默认$HOME/.config/openglm/config
+
+密钥依次从OPENGLM_API_KEY环境变量和配置文件的api_key中读取。
+
+交互命令: /save FILE, /load FILE, /undo, /reset, /exit
+/load会停止--save指定的自动保存，避免加载的对话写入其他文件。";
+
+type CliResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const API_KEY_ENV: &str = "OPENGLM_API_KEY";
+const DEFAULT_MODEL: &str = "glm-4-flash";
+
+fn fail(message: &str) -> ! {
+    eprintln!("openglm: {}", message);
+    process::exit(2);
+}
+
+#[derive(Default)]
+struct Options {
+    model: Option<String>,
+    temperature: Option<f32>,
+    system: Option<String>,
+    json: bool,
+    stream: bool,
+    load: Option<PathBuf>,
+    save: Option<PathBuf>,
+    base_url: Option<String>,
+    config: Option<PathBuf>,
+    prompt: Vec<String>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options { stream: true, ..Default::default() };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("missing value for {}", arg)));
+        match arg.as_str() {
+            "-m" | "--model" => options.model = Some(value()),
+            "-t" | "--temperature" => options.temperature = Some(value().parse().unwrap_or_else(|_| fail("invalid --temperature"))),
+            "-s" | "--system" => options.system = Some(value()),
+            "--json" => options.json = true,
+            "--no-stream" => options.stream = false,
+            "--load" => options.load = Some(value().into()),
+            "--save" => options.save = Some(value().into()),
+            "--base-url" => options.base_url = Some(value()),
+            "--config" => options.config = Some(value().into()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            "--" => options.prompt.extend(args.by_ref()),
+            _ if arg.starts_with('-') => fail(&format!("unknown argument {}\n\n{}", arg, USAGE)),
+            _ => options.prompt.push(arg),
+        }
+    }
+
+    options
+}
+
+/// 配置文件每行一个`key = value`，支持api_key、model、base_url，#开头为注释
+#[derive(Default)]
+struct Config {
+    api_key: Option<String>,
+    model: Option<String>,
+    base_url: Option<String>,
+}
+
+fn load_config(path: Option<&PathBuf>) -> Config {
+    let path = match path {
+        Some(path) => path.clone(),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config/openglm/config"),
+            None => return Config::default(),
+        },
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        // 默认配置文件不存在时忽略
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Config::default(),
+        Err(e) => fail(&format!("failed to read {}: {}", path.display(), e)),
+    };
+
+    let mut config = Config::default();
+    for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let Some((key, value)) = line.split_once('=') else {
+            fail(&format!("invalid line in {}: {}", path.display(), line));
+        };
+        let value = Some(value.trim().trim_matches('"').to_string());
+        match key.trim() {
+            "api_key" => config.api_key = value,
+            "model" => config.model = value,
+            "base_url" => config.base_url = value,
+            key => fail(&format!("unknown key in {}: {}", path.display(), key)),
+        }
+    }
+
+    config
+}
+
+fn load_transcript(path: &PathBuf) -> CliResult<Conversation> {
+    let content = std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+fn save_transcript(path: &PathBuf, conversation: &Conversation) -> CliResult<()> {
+    let content = serde_json::to_vec_pretty(conversation)?;
+    std::fs::write(path, content).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+struct Session {
+    client: OpenGLM,
+    conversation: Conversation,
+    temperature: Option<f32>,
+    json: bool,
+    stream: bool,
+    save: Option<PathBuf>,
+}
+
+impl Session {
+    fn request(&self) -> CompletionsRequestBuilder {
+        let request = self.conversation.request(&self.client);
+        match self.temperature {
+            Some(temperature) => request.with_temperature(temperature),
+            None => request,
+        }
+    }
+
+    /// 发送一轮对话并输出回复，失败时撤销本轮输入；流式输出中途失败时保留已输出的内容
+    async fn turn(&mut self, input: String) -> CliResult<()> {
+        self.conversation.push_user(ChatMessage::User(input));
+
+        let result = match self.stream {
+            true => self.stream_turn().await,
+            false => self.send_turn().await.inspect_err(|_| {
+                self.conversation.undo();
+            }),
+        };
+
+        if let Some(path) = &self.save {
+            save_transcript(path, &self.conversation)?;
+        }
+        Ok(result?)
+    }
+
+    async fn send_turn(&mut self) -> Result<()> {
+        let response = self.request().send_with_response().await?;
+        if self.json {
+            println!("{}", String::from_utf8_lossy(&response.body));
+        } else if let Some(choice) = response.value.choices.first() {
+            match &choice.message {
+                ChatMessage::Assistant(content) => println!("{}", content),
+                message => println!("{}", serde_json::to_string(message)?),
+            }
+        }
+
+        self.conversation.record(&response.value)
+    }
+
+    // 自行决定是否撤销本轮输入：超时等错误发生前已输出的内容追加到对话中
+    async fn stream_turn(&mut self) -> Result<()> {
+        let mut iter = match self.request().stream().send().await {
+            Ok(iter) => iter,
+            Err(e) => {
+                self.conversation.undo();
+                return Err(e);
+            },
+        };
+        let mut stdout = io::stdout().lock();
+        let result = loop {
+            let chunk = match iter.next().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            if self.json {
+                let line = iter.last_line().unwrap_or_default();
+                let _ = writeln!(stdout, "{}", line.strip_prefix("data: ").unwrap_or(line));
+                continue;
+            }
+
+            for choice in chunk.choices.iter().filter(|choice| choice.index == 0) {
+                if let AssistantMessageDelta::Content(content) = &choice.delta {
+                    let _ = write!(stdout, "{}", content);
+                    let _ = stdout.flush();
+                }
+            }
+        };
+        if !self.json {
+            let _ = writeln!(stdout);
+        }
+
+        // 没有输出内容，或只收到了不完整的工具调用时无法合并为消息，撤销本轮输入
+        let recorded = self.conversation.record_deltas(iter.accumulated().to_vec());
+        if recorded.is_err() {
+            self.conversation.undo();
+        }
+        result.and(recorded)
+    }
+
+    /// 处理交互命令，返回false表示退出
+    fn command(&mut self, line: &str) -> CliResult<bool> {
+        let (command, argument) = line.split_once(' ').map(|(command, argument)| (command, argument.trim())).unwrap_or((line, ""));
+        match command {
+            "/exit" | "/quit" => return Ok(false),
+            "/undo" => {
+                if self.conversation.undo().is_none() {
+                    eprintln!("nothing to undo");
+                }
+            },
+            "/reset" => {
+                let mut conversation = Conversation::new(self.conversation.model().clone());
+                if let Some(system) = self.conversation.system() {
+                    conversation = conversation.with_system(system.to_string());
+                }
+                self.conversation = conversation;
+            },
+            "/save" if !argument.is_empty() => save_transcript(&argument.into(), &self.conversation)?,
+            "/load" if !argument.is_empty() => {
+                self.conversation = load_transcript(&argument.into())?;
+                // 与--load一致，不会写回加载的文件，也不再写入之前的--save文件
+                if self.save.take().is_some() {
+                    eprintln!("autosave disabled, use /save FILE to save the loaded conversation");
+                }
+            },
+            _ => eprintln!("commands: /save FILE, /load FILE, /undo, /reset, /exit"),
+        }
+        Ok(true)
+    }
+
+    async fn repl(&mut self) {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("> ");
+            let _ = io::stdout().flush();
+
+            let Some(Ok(line)) = lines.next() else {
+                println!();
+                return;
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let result = match line.starts_with('/') {
+                true => self.command(line),
+                false => self.turn(line.to_string()).await.map(|_| true),
+            };
+            match result {
+                Ok(true) => {},
+                Ok(false) => return,
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let options = parse_args();
+    let config = load_config(options.config.as_ref());
+
+    let api_key = std::env::var(API_KEY_ENV).ok().filter(|api_key| !api_key.is_empty()).or(config.api_key)
+        .unwrap_or_else(|| fail(&format!("no api key, set {} or api_key in the config file", API_KEY_ENV)));
+    let mut client = OpenGLM::new(api_key);
+    if let Some(base_url) = options.base_url.or(config.base_url) {
+        client = client.with_base_url(base_url);
+    }
+
+    let mut conversation = match &options.load {
+        Some(path) => load_transcript(path).unwrap_or_else(|e| fail(&e.to_string())),
+        None => Conversation::new(config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string())),
+    };
+    // 命令行参数优先于记录文件和配置文件
+    if let Some(model) = options.model {
+        conversation = conversation.with_model(model);
+    }
+    if let Some(system) = options.system {
+        conversation = conversation.with_system(system);
+    }
+
+    let mut session = Session {
+        client,
+        conversation,
+        temperature: options.temperature,
+        json: options.json,
+        stream: options.stream,
+        save: options.save,
+    };
+
+    let prompt = match options.prompt.is_empty() {
+        false => Some(options.prompt.join(" ")),
+        true if !io::stdin().is_terminal() => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).unwrap_or_else(|e| fail(&format!("failed to read stdin: {}", e)));
+            Some(input.trim_end().to_string())
+        },
+        true => None,
+    };
+
+    match prompt {
+        Some(prompt) => {
+            if let Err(e) = session.turn(prompt).await {
+                fail(&e.to_string());
+            }
+        },
+        None => session.repl().await,
+    }
+}
@@ -0,0 +1,383 @@
+use std::{collections::HashMap, fs, path::PathBuf, process, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::{Duration, Instant, SystemTime}};
+
+use bytes::Bytes;
+use ring::digest;
+use serde_json::json;
+
+use crate::chat::completions::request_inner::RequestInner;
+use crate::chat::completions::result::{CompletionChoice, CompletionResult};
+use crate::chat::message::ChatMessage;
+use crate::error::Result;
+use crate::http::decode;
+use crate::openglm::OpenGLM;
+
+/// 缓存的存储后端，值为接口返回的原始JSON
+pub trait CacheBackend: Send + Sync {
+    fn get(&self, key: &str) -> Option<Bytes>;
+
+    fn put(&self, key: &str, value: Bytes);
+
+    /// 移除条目，如无法解析的缓存值
+    fn remove(&self, key: &str);
+}
+
+struct MemoryEntry {
+    value: Bytes,
+    inserted: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    entries: HashMap<String, MemoryEntry>,
+    clock: u64,
+}
+
+/// 内存中的LRU缓存，超过容量时移除最久未使用的条目
+pub struct MemoryCache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    /// 条目写入后的有效期，默认不过期
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+
+        let entry = state.entries.get_mut(key)?;
+        if self.ttl.is_some_and(|ttl| entry.inserted.elapsed() >= ttl) {
+            state.entries.remove(key);
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.value.clone())
+    }
+
+    fn put(&self, key: &str, value: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+
+        state.entries.insert(key.to_string(), MemoryEntry { value, inserted: Instant::now(), last_used: clock });
+        while state.entries.len() > self.capacity {
+            let Some(oldest) = state.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()) else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        self.state.lock().unwrap().entries.remove(key);
+    }
+}
+
+/// 目录中每个条目一个文件，可在多次运行之间复用
+pub struct DiskCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+}
+
+impl DiskCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl: None,
+        }
+    }
+
+    /// 按文件修改时间判断是否过期，默认不过期
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+impl CacheBackend for DiskCache {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let path = self.path(key);
+        if let Some(ttl) = self.ttl {
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+            if SystemTime::now().duration_since(modified).unwrap_or_default() >= ttl {
+                return None;
+            }
+        }
+        fs::read(path).ok().map(Bytes::from)
+    }
+
+    // 先写入临时文件再重命名，并发写入或进程中断时不会留下不完整的条目；写入失败只会导致下次未命中，不影响请求
+    fn put(&self, key: &str, value: Bytes) {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let _ = fs::create_dir_all(&self.dir);
+        let temp = self.dir.join(format!(".{}.{}.{}.tmp", key, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        if fs::write(&temp, value).and_then(|_| fs::rename(&temp, self.path(key))).is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.path(key));
+    }
+}
+
+/// 客户端的响应缓存：以规范化后的请求体为键，缓存对话接口的结果。
+///
+/// 默认只缓存确定性的请求（do_sample为false或temperature为0），流式请求命中时按缓存结果重放。
+/// 由FallbackPolicy降级到其他模型得到的结果不缓存。
+#[derive(Clone)]
+pub struct ResponseCache {
+    backend: Arc<dyn CacheBackend>,
+    cache_sampled: bool,
+}
+
+impl ResponseCache {
+    pub fn new(backend: impl CacheBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            cache_sampled: false,
+        }
+    }
+
+    /// 同时缓存开启采样的请求
+    pub fn with_sampled(self, cache_sampled: bool) -> Self {
+        Self {
+            cache_sampled,
+            ..self
+        }
+    }
+
+    /// 请求不可缓存时返回None
+    pub(crate) fn key(&self, inner: &RequestInner) -> Result<Option<String>> {
+        if !self.cache_sampled && !inner.is_deterministic() {
+            return Ok(None);
+        }
+
+        // request_id每次不同，stream不影响结果
+        let mut body = inner.to_body(false)?;
+        if let Some(object) = body.as_object_mut() {
+            object.remove("request_id");
+            object.remove("stream");
+        }
+        let canonical = serde_json::to_vec(&body)?;
+        let hash = digest::digest(&digest::SHA256, &canonical);
+        Ok(Some(hash.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()))
+    }
+
+    pub(crate) fn put(&self, key: &str, value: Bytes) {
+        self.backend.put(key, value)
+    }
+
+    /// 读取并解析缓存的结果；无法解析时（如写入不完整）移除该条目，按未命中处理
+    pub(crate) fn get_result(&self, key: &str) -> Option<(Bytes, CompletionResult<CompletionChoice>)> {
+        let body = self.backend.get(key)?;
+        match decode(&body) {
+            Ok(result) => Some((body, result)),
+            Err(_) => {
+                self.backend.remove(key);
+                None
+            },
+        }
+    }
+}
+
+/// 客户端开启了缓存且请求可缓存时，返回缓存和键
+pub(crate) fn lookup(client: &OpenGLM, inner: &RequestInner) -> Result<Option<(ResponseCache, String)>> {
+    let Some(cache) = client.cache() else {
+        return Ok(None);
+    };
+    Ok(cache.key(inner)?.map(|key| (cache.clone(), key)))
+}
+
+/// 将缓存的结果转换为SSE数据，每个choice一个片段，最后附带usage
+pub(crate) fn to_event_stream(result: &CompletionResult<CompletionChoice>) -> Result<Bytes> {
+    let mut events = String::new();
+    let last = result.choices.len().saturating_sub(1);
+    for (position, choice) in result.choices.iter().enumerate() {
+        let mut delta = serde_json::to_value(&choice.message)?;
+        if let ChatMessage::ToolCall(tool_calls) = &choice.message {
+            delta["tool_calls"] = tool_calls.iter().enumerate().map(|(index, tool_call)| {
+                let mut value = serde_json::to_value(tool_call)?;
+                value["index"] = json!(index);
+                Ok(value)
+            }).collect::<Result<_>>()?;
+        }
+
+        let mut chunk = json!({
+            "id": result.id,
+            "created": result.created,
+            "model": result.model,
+            "choices": [{"index": choice.index, "finish_reason": choice.finish_reason, "delta": delta}],
+        });
+        if position == last {
+            if let Some(usage) = &result.usage {
+                chunk["usage"] = serde_json::to_value(usage)?;
+            }
+        }
+        events.push_str(&format!("data: {}\n\n", chunk));
+    }
+    events.push_str("data: [DONE]\n\n");
+
+    Ok(Bytes::from(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_memory_cache() {
+        let cache = MemoryCache::new(2);
+        cache.put("a", Bytes::from_static(b"1"));
+        cache.put("b", Bytes::from_static(b"2"));
+        assert!(cache.get("a").is_some());
+        cache.put("c", Bytes::from_static(b"3"));
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.len(), 2);
+
+        cache.remove("a");
+        assert!(cache.get("a").is_none());
+
+        let cache = MemoryCache::new(2).with_ttl(Duration::ZERO);
+        cache.put("a", Bytes::from_static(b"1"));
+        assert!(cache.get("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let transport = Arc::new(MockTransport::new().with_response(MockResponse::json(&json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12},
+        }))));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string())
+            .with_transport(transport.clone())
+            .with_cache(ResponseCache::new(MemoryCache::new(16)));
+        let request = || client.chat().completions().create()
+            .with_model(Model::Glm4)
+            .with_do_sample(false)
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        let first = request().with_request_id("request-1".to_string()).send_with_response().await.unwrap();
+        assert!(!first.meta.cached);
+        let second = request().with_request_id("request-2".to_string()).send_with_response().await.unwrap();
+        assert!(second.meta.cached);
+        assert_eq!(second.body, first.body);
+
+        let mut iter = request().stream().send().await.unwrap();
+        while iter.next().await.unwrap().is_some() {}
+        assert!(matches!(iter.partial_message().unwrap(), ChatMessage::Assistant(ref content) if content == "你好"));
+        assert_eq!(transport.requests().len(), 1);
+
+        // 流式请求正常结束后写入缓存
+        transport.push(MockResponse::sse([json!({
+            "id": "2", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "delta": {"role": "assistant", "content": "再见"}}],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12},
+        })]));
+        let farewell = || request().add_message(ChatMessage::Assistant("你好".to_string())).add_message(ChatMessage::User("再见".to_string()));
+        let mut iter = farewell().stream().send().await.unwrap();
+        while iter.next().await.unwrap().is_some() {}
+        let result = farewell().send_with_response().await.unwrap();
+        assert!(result.meta.cached);
+        assert!(matches!(result.value.choices[0].message, ChatMessage::Assistant(ref content) if content == "再见"));
+        assert_eq!(transport.requests().len(), 2);
+
+        // 未关闭采样的请求不缓存
+        let err = client.chat().completions().create()
+            .with_model(Model::Glm4)
+            .add_message(ChatMessage::User("你好".to_string()))
+            .send().await.unwrap_err();
+        assert!(matches!(err, Error::Transport(_)));
+    }
+
+    #[tokio::test]
+    async fn test_replay_not_counted() {
+        let transport = MockTransport::new().with_response(MockResponse::json(&json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12},
+        })));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string())
+            .with_transport(transport)
+            .with_rate_limiter(RateLimiter::new().with_tokens_per_minute(20).unwrap())
+            .with_cache(ResponseCache::new(MemoryCache::new(16)));
+        let request = || client.chat().completions().create()
+            .with_model(Model::Glm4)
+            .with_do_sample(false)
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        request().send().await.unwrap();
+        for _ in 0..2 {
+            let mut iter = request().stream().send().await.unwrap();
+            while iter.next().await.unwrap().is_some() {}
+        }
+
+        // 只有实际发出的请求消耗token额度
+        let acquired = tokio::time::timeout(Duration::from_millis(20), client.rate_limiter().unwrap().acquire()).await;
+        assert!(acquired.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_entry_is_miss() {
+        let dir = std::env::temp_dir().join(format!("openglm-cache-test-{}", process::id()));
+        let completion = || MockResponse::json(&json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+        }));
+        let transport = Arc::new(MockTransport::new().with_response(completion()).with_response(completion()));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string())
+            .with_transport(transport.clone())
+            .with_cache(ResponseCache::new(DiskCache::new(&dir)));
+        let request = || client.chat().completions().create()
+            .with_model(Model::Glm4)
+            .with_do_sample(false)
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        request().send().await.unwrap();
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(entries.len(), 1);
+        fs::write(&entries[0], "{\"id\": ").unwrap();
+
+        // 不完整的条目按未命中处理，重新请求后写回
+        assert!(!request().send_with_response().await.unwrap().meta.cached);
+        assert!(request().send_with_response().await.unwrap().meta.cached);
+        assert_eq!(transport.requests().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
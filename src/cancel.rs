@@ -0,0 +1,79 @@
+use std::{future::Future, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+use tokio::sync::Notify;
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// 用于取消进行中的请求，克隆出的token共享同一状态
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        self.state.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// 等待token被取消
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.state.notify.notified();
+            tokio::pin!(notified);
+            // 先注册再检查，避免错过检查之后、等待之前发生的取消
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken").field("cancelled", &self.is_cancelled()).finish()
+    }
+}
+
+/// 等待future完成，token被取消时返回None，future随之被丢弃
+pub(crate) async fn with_cancel<F: Future>(future: F, token: Option<&CancellationToken>) -> Option<F::Output> {
+    let Some(token) = token else {
+        return Some(future.await);
+    };
+
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => None,
+        output = future => Some(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_cancel() {
+        let token = CancellationToken::new();
+        assert_eq!(with_cancel(async { 1 }, Some(&token)).await, Some(1));
+
+        let cloned = token.clone();
+        cloned.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(with_cancel(std::future::pending::<()>(), Some(&token)).await, None);
+    }
+}
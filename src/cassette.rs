@@ -0,0 +1,315 @@
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use bytes::{Bytes, BytesMut};
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION}, StatusCode};
+
+use crate::error::{Error, Result};
+use crate::http::{HttpRequest, HttpResponse, ResponseBody};
+use crate::send::BoxFuture;
+use crate::transport::{HttpTransport, MockBody};
+
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    /// 请求体为JSON时按JSON保存，便于比较和阅读
+    pub body: serde_json::Value,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    /// 按收到的顺序保存的响应体分块，流式响应即为SSE数据
+    pub chunks: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// 录制的请求和响应，保存为JSON文件
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let content = fs::read(path.as_ref()).map_err(|e| Error::Transport(format!("failed to read cassette {}: {}", path.as_ref().display(), e)))?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(path.as_ref(), content).map_err(|e| Error::Transport(format!("failed to write cassette {}: {}", path.as_ref().display(), e)))
+    }
+}
+
+fn record_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers.iter().map(|(name, value)| {
+        let value = if name == AUTHORIZATION { REDACTED.to_string() } else { String::from_utf8_lossy(value.as_bytes()).into_owned() };
+        (name.to_string(), value)
+    }).collect()
+}
+
+fn record_body(body: &Bytes) -> serde_json::Value {
+    serde_json::from_slice(body).unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+fn record_request(request: &HttpRequest) -> RecordedRequest {
+    RecordedRequest {
+        method: request.method.to_string(),
+        url: request.url.clone(),
+        headers: record_headers(&request.headers),
+        body: record_body(&request.body),
+    }
+}
+
+/// 包裹实际的transport，将经过的请求和响应录制到cassette文件中。
+///
+/// 流式响应在读取时同步录制，全部请求结束后调用save写入文件。
+pub struct RecordingTransport<T> {
+    inner: T,
+    path: PathBuf,
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+}
+
+impl <T: HttpTransport> RecordingTransport<T> {
+    pub fn new(inner: T, path: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            path: path.into(),
+            interactions: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn cassette(&self) -> Cassette {
+        Cassette { interactions: self.interactions.lock().unwrap().clone() }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.cassette().save(&self.path)
+    }
+}
+
+// 边读取边录制的响应体，跨块的多字节字符留到下一块再保存
+struct RecordingBody {
+    inner: Box<dyn ResponseBody>,
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+    index: usize,
+    pending: BytesMut,
+}
+
+impl RecordingBody {
+    fn record(&mut self, chunk: Option<&Bytes>) {
+        let text = match chunk {
+            Some(chunk) => {
+                self.pending.extend_from_slice(chunk);
+                let valid = match std::str::from_utf8(&self.pending) {
+                    Ok(_) => self.pending.len(),
+                    Err(e) => e.valid_up_to(),
+                };
+                let valid = self.pending.split_to(valid);
+                String::from_utf8_lossy(&valid).into_owned()
+            },
+            None => {
+                let rest = self.pending.split();
+                String::from_utf8_lossy(&rest).into_owned()
+            },
+        };
+
+        if !text.is_empty() {
+            self.interactions.lock().unwrap()[self.index].response.chunks.push(text);
+        }
+    }
+}
+
+impl ResponseBody for RecordingBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Bytes>>> {
+        Box::pin(async move {
+            let chunk = self.inner.chunk().await?;
+            self.record(chunk.as_ref());
+            Ok(chunk)
+        })
+    }
+}
+
+impl <T: HttpTransport> HttpTransport for RecordingTransport<T> {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        Box::pin(async move {
+            let recorded = record_request(&request);
+            let response = self.inner.send(request).await?;
+
+            let index = {
+                let mut interactions = self.interactions.lock().unwrap();
+                interactions.push(Interaction {
+                    request: recorded,
+                    response: RecordedResponse {
+                        status: response.status.as_u16(),
+                        headers: record_headers(&response.headers),
+                        chunks: Vec::new(),
+                    },
+                });
+                interactions.len() - 1
+            };
+
+            Ok(HttpResponse {
+                status: response.status,
+                headers: response.headers,
+                body: Box::new(RecordingBody {
+                    inner: response.body,
+                    interactions: self.interactions.clone(),
+                    index,
+                    pending: BytesMut::new(),
+                }),
+            })
+        })
+    }
+}
+
+/// 按cassette回放响应，不访问网络。
+///
+/// 请求按method、url和请求体匹配尚未使用的录制；没有匹配时返回错误。
+/// 结束时应调用finish检查是否所有录制都被使用，否则在drop时panic。
+pub struct ReplayTransport {
+    interactions: Vec<Interaction>,
+    used: Mutex<Vec<bool>>,
+    finished: Mutex<bool>,
+}
+
+impl ReplayTransport {
+    pub fn new(cassette: Cassette) -> Self {
+        let used = vec![false; cassette.interactions.len()];
+        Self {
+            interactions: cassette.interactions,
+            used: Mutex::new(used),
+            finished: Mutex::new(false),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(Cassette::load(path)?))
+    }
+
+    fn unused(&self) -> Vec<&RecordedRequest> {
+        let used = self.used.lock().unwrap();
+        self.interactions.iter().zip(used.iter())
+            .filter(|(_, used)| !**used)
+            .map(|(interaction, _)| &interaction.request)
+            .collect()
+    }
+
+    /// 检查所有录制的请求都已被回放
+    pub fn finish(&self) -> Result<()> {
+        *self.finished.lock().unwrap() = true;
+
+        let unused = self.unused();
+        if unused.is_empty() {
+            return Ok(());
+        }
+
+        let bodies: Vec<String> = unused.iter().map(|request| format!("{} {} {}", request.method, request.url, request.body)).collect();
+        Err(Error::Transport(format!("{} recorded interaction(s) were never requested: {}", unused.len(), bodies.join("; "))))
+    }
+}
+
+impl HttpTransport for ReplayTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        Box::pin(async move {
+            let recorded = record_request(&request);
+            let interaction = {
+                let mut used = self.used.lock().unwrap();
+                let found = self.interactions.iter().enumerate().find(|(i, interaction)| {
+                    !used[*i]
+                        && interaction.request.method == recorded.method
+                        && interaction.request.url == recorded.url
+                        && interaction.request.body == recorded.body
+                });
+                let Some((i, interaction)) = found else {
+                    return Err(Error::Transport(format!("no recorded interaction matches {} {} {}", recorded.method, recorded.url, recorded.body)));
+                };
+                used[i] = true;
+                interaction
+            };
+
+            let response = &interaction.response;
+            let mut headers = HeaderMap::new();
+            for (name, value) in &response.headers {
+                if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value.as_str())) {
+                    headers.append(name, value);
+                }
+            }
+
+            Ok(HttpResponse {
+                status: StatusCode::from_u16(response.status).map_err(|e| Error::Transport(e.to_string()))?,
+                headers,
+                body: Box::new(MockBody::new(response.chunks.iter().map(|chunk| Bytes::from(chunk.clone())).collect(), None)),
+            })
+        })
+    }
+}
+
+impl Drop for ReplayTransport {
+    fn drop(&mut self) {
+        if std::thread::panicking() || *self.finished.lock().unwrap() {
+            return;
+        }
+
+        let unused = self.unused().len();
+        if unused > 0 {
+            panic!("{} recorded interaction(s) were never requested", unused);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::completions::RequestBuild;
+    use crate::chat::message::ChatMessage;
+    use crate::openglm::OpenGLM;
+    use crate::send::Sendable;
+    use crate::transport::{MockResponse, MockTransport};
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let chunks = ["data: {\"id\":\"1\",\"created\":1,\"model\":\"glm-4\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"你好\"}}]}\n\n".as_bytes().to_vec(), b"data: [DONE]\n\n".to_vec()];
+        // 在多字节字符中间切开，验证录制时不会损坏内容
+        let mut joined = chunks.concat();
+        let tail = joined.split_off(70);
+        let mock = MockTransport::new().with_response(MockResponse::chunked(StatusCode::OK, [joined, tail]));
+
+        // 每个测试进程使用不同的文件，避免并行运行时互相覆盖
+        let path = std::env::temp_dir().join(format!("openglm-cassette-test-{}.json", std::process::id()));
+        let recorder = Arc::new(RecordingTransport::new(mock, path.clone()));
+        let client = OpenGLM::new("id.secret".to_string()).with_transport(recorder.clone());
+        let request = |client: &OpenGLM| client.chat().completions().create()
+            .with_model("glm-4")
+            .add_message(ChatMessage::User("你好".to_string()))
+            .stream();
+
+        let mut iter = request(&client).send().await.unwrap();
+        while iter.next().await.unwrap().is_some() {}
+
+        recorder.save().unwrap();
+        let cassette = Cassette::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cassette.interactions[0].request.headers["authorization"], REDACTED);
+        assert_eq!(cassette.interactions[0].response.chunks.concat().as_bytes(), chunks.concat());
+
+        let replay = Arc::new(ReplayTransport::new(cassette));
+        let client = OpenGLM::new("id.secret".to_string()).with_transport(replay.clone());
+        assert!(replay.finish().is_err());
+
+        let mut iter = request(&client).send().await.unwrap();
+        assert!(iter.next().await.unwrap().is_some());
+        assert!(request(&client).send().await.is_err());
+        replay.finish().unwrap();
+    }
+}
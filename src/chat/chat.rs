@@ -1,33 +1,43 @@
-use crate::chat::completions::completions::CompletionsRequestBuilder;
+use crate::chat::completions::{completions::CompletionsRequestBuilder, typed::{NoMessages, NoModel, TypedCompletionsRequestBuilder}};
+use crate::openglm::OpenGLM;
 
 pub struct Chat {
-    api_key: String,
+    client: OpenGLM,
 }
 
 impl Chat {
-    pub(crate) fn new(api_key: String) -> Self {
+    pub(crate) fn new(client: OpenGLM) -> Self {
         Self {
-            api_key,
+            client,
         }
     }
 
     pub fn completions(self) -> Completions {
-        Completions::new(self.api_key)
+        Completions::with_client(self.client)
     }
 }
 
 pub struct Completions {
-    api_key: String,
+    client: OpenGLM,
 }
 
 impl Completions {
     pub fn new(api_key: String) -> Self {
+        Self::with_client(OpenGLM::new(api_key))
+    }
+
+    pub(crate) fn with_client(client: OpenGLM) -> Self {
         Self {
-            api_key,
+            client,
         }
     }
 
     pub fn create(self) -> CompletionsRequestBuilder {
-        CompletionsRequestBuilder::new(self.api_key.clone())
+        CompletionsRequestBuilder::new(self.client)
+    }
+
+    /// 与create相同，但未设置model和messages时无法编译通过
+    pub fn create_typed(self) -> TypedCompletionsRequestBuilder<NoModel, NoMessages> {
+        TypedCompletionsRequestBuilder::new(self.client)
     }
 }
@@ -1,6 +1,17 @@
-use crate::authen::generate;
+use std::time::Instant;
+
+use crate::authen::Auth;
+use crate::cache::lookup;
+use crate::fallback::with_fallback;
+use crate::http::{decode, post_completions, Exchange};
+use crate::openglm::OpenGLM;
+use crate::timeout::{with_timeout, TimeoutPhase};
+use crate::response::{Response, ResponseMeta};
 use crate::send::Sendable;
-use crate::error::{Result, Error};
+use crate::telemetry::RequestSpan;
+use crate::usage::UsageScope;
+use crate::cancel::with_cancel;
+use crate::error::{Error, Result};
 
 use super::request_inner::RequestInner;
 use super::result::{CompletionChoice, CompletionResult};
@@ -8,32 +19,84 @@ use super::stream_completions::StreamCompletionsRequest;
 use super::Unpack;
 
 pub struct CompletionsRequestBuilder {
-    api_key: String,
+    client: OpenGLM,
     inner: RequestInner,
 }
 
 impl CompletionsRequestBuilder {
-    pub(crate) fn new(api_key: String) -> Self {
+    pub(crate) fn new(client: OpenGLM) -> Self {
         Self {
-            api_key,
+            client,
             inner: RequestInner::new(),
         }
     }
 
     pub fn stream(self) -> StreamCompletionsRequest {
-        StreamCompletionsRequest::new_with(self.api_key, self.inner)
+        StreamCompletionsRequest::new_with(self.client, self.inner)
+    }
+
+    /// 与send相同，但同时返回状态码、响应头、耗时和原始响应体
+    pub async fn send_with_response(self) -> Result<Response<CompletionResult<CompletionChoice>>> {
+        let span = RequestSpan::start(&self.client, &self.inner, false);
+        let result = span.instrument(self.send_cached()).await;
+        match &result {
+            Ok(response) => span.record_response(response),
+            Err(e) => span.record_error(e),
+        }
+        result
+    }
+
+    async fn send_cached(self) -> Result<Response<CompletionResult<CompletionChoice>>> {
+        let cached = lookup(&self.client, &self.inner)?;
+        if let Some((body, value)) = cached.as_ref().and_then(|(cache, key)| cache.get_result(key)) {
+            return Ok(Response { meta: ResponseMeta::cached(self.inner.model().cloned()), body, value });
+        }
+
+        let response = with_fallback(self.client, self.inner, send_once).await?;
+        // 降级后的结果来自其他模型，不缓存在所请求模型的键下
+        if let (Some((cache, key)), true) = (cached, response.meta.fallbacks.is_empty()) {
+            cache.put(&key, response.body.clone());
+        }
+        Ok(response)
+    }
+}
+
+async fn send_once(client: OpenGLM, mut inner: RequestInner, deadline: Option<Instant>) -> Result<Response<CompletionResult<CompletionChoice>>> {
+    inner.validate(client.auth() == Auth::Jwt, false)?;
+    inner.truncate().await?;
+    let usage = UsageScope::new(&client, &inner);
+    if let Some(usage) = &usage {
+        usage.check_budgets(&inner)?;
+    }
+
+    let Exchange { response, meta, deadline, permit, lease } = post_completions(&client, &inner, false, deadline).await?;
+    let body = with_timeout(response.bytes(), None, deadline, TimeoutPhase::Deadline);
+    let body = with_cancel(body, inner.cancellation()).await.ok_or(Error::Cancelled)???;
+    drop(permit);
+
+    let value: CompletionResult<CompletionChoice> = decode(&body)?;
+    if let (Some(rate_limiter), Some(usage)) = (client.rate_limiter(), &value.usage) {
+        rate_limiter.record_usage(usage.total_tokens.max(0) as u64);
     }
+    if let (Some(scope), Some(usage)) = (usage, &value.usage) {
+        scope.with_lease(lease.as_ref()).record(usage);
+    }
+    if let (Some(lease), Some(usage)) = (lease, &value.usage) {
+        lease.record_usage(usage);
+    }
+
+    Ok(Response { meta, body, value })
 }
 
 impl Unpack for CompletionsRequestBuilder {
-    type ExtType = String;
+    type ExtType = OpenGLM;
 
     fn unpack(self) -> (RequestInner, Self::ExtType) {
-        (self.inner, self.api_key)
+        (self.inner, self.client)
     }
 
     fn pack(inner: RequestInner, ext: Self::ExtType) -> Self {
-        Self { api_key: ext, inner }
+        Self { client: ext, inner }
     }
 }
 
@@ -41,20 +104,6 @@ impl Sendable for CompletionsRequestBuilder {
     type Output = CompletionResult<CompletionChoice>;
 
     async fn send(self) -> Result<Self::Output> {
-        if !self.inner.is_requestable() {
-            return Err(Error::MissingParams);
-        }
-
-        let token = generate(&self.api_key)?;
-
-        let ret = reqwest::Client::new()
-            .post("https://open.bigmodel.cn/api/paas/v4/chat/completions")
-            .header("Authorization", format!("Bearer {}", &token))
-            .json(&self.inner)
-            .send()
-            .await?
-            .json::<CompletionResult<CompletionChoice>>()
-            .await?;
-        Ok(ret)
+        Ok(self.send_with_response().await?.value)
     }
 }
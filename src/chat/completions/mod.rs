@@ -1,6 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod completions;
 pub mod stream_completions;
 pub mod request_inner;
 pub mod result;
+pub mod typed;
+pub mod validation;
 
 pub use request_inner::{Unpack, RequestBuild};
\ No newline at end of file
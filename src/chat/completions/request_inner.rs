@@ -1,8 +1,16 @@
-use crate::chat::{message::ChatMessage, tools::*};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
-#[derive(serde::Serialize)]
+use crate::chat::{context::{input_budget, TruncationStrategy}, message::ChatMessage, tools::*};
+use crate::error::{Error, Result};
+use crate::model::Model;
+use crate::cancel::CancellationToken;
+use crate::timeout::Timeouts;
+
+use super::validation::{check_function_parameters, check_header, is_valid_request_id, ValidationError, ValidationIssue, MAX_STOP_WORDS};
+
+#[derive(serde::Serialize, Clone)]
 pub struct RequestInner {
-    model: Option<String>,
+    model: Option<Model>,
     messages: Option<Vec<ChatMessage>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     request_id: Option<String>,
@@ -20,6 +28,48 @@ pub struct RequestInner {
     tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_choice: Option<String>,
+    #[serde(skip)]
+    truncation: Option<Arc<dyn TruncationStrategy>>,
+    #[serde(skip)]
+    extra_body: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip)]
+    headers: Vec<(String, String)>,
+    #[serde(skip)]
+    timeouts: Timeouts,
+    #[serde(skip)]
+    cancellation: Option<CancellationToken>,
+    #[serde(skip)]
+    usage_tag: Option<String>,
+}
+
+// 已有类型化设置方法的请求体字段，不能通过with_extra_body覆盖
+const TYPED_FIELDS: [&str; 11] = [
+    "model", "messages", "request_id", "do_sample", "temperature", "top_p",
+    "max_tokens", "stop", "tools", "tool_choice", "stream",
+];
+
+// 供tracing记录请求参数
+#[cfg(feature = "tracing")]
+impl RequestInner {
+    pub(crate) fn messages(&self) -> &[ChatMessage] {
+        self.messages.as_deref().unwrap_or_default()
+    }
+
+    pub(crate) fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub(crate) fn temperature(&self) -> Option<f32> {
+        self.temperature
+    }
+
+    pub(crate) fn top_p(&self) -> Option<f32> {
+        self.top_p
+    }
+
+    pub(crate) fn max_tokens(&self) -> Option<i32> {
+        self.max_tokens
+    }
 }
 
 impl RequestInner {
@@ -35,14 +85,192 @@ impl RequestInner {
             stop: None,
             tools: None,
             tool_choice: None,
+            truncation: None,
+            extra_body: serde_json::Map::new(),
+            headers: Vec::new(),
+            timeouts: Timeouts::default(),
+            cancellation: None,
+            usage_tag: None,
+        }
+    }
+
+    /// 序列化请求体，并合并with_extra_body设置的字段
+    pub(crate) fn to_body(&self, stream: bool) -> Result<serde_json::Value> {
+        let mut body = serde_json::to_value(self)?;
+        let object = body.as_object_mut().expect("request body is a JSON object");
+        if stream {
+            object.insert("stream".to_string(), serde_json::Value::Bool(true));
+        }
+        for (key, value) in &self.extra_body {
+            object.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+
+        Ok(body)
+    }
+
+    /// 关闭了采样或温度为0，相同的请求会得到相同的结果
+    pub(crate) fn is_deterministic(&self) -> bool {
+        self.do_sample == Some(false) || self.temperature == Some(0.0)
+    }
+
+    pub(crate) fn usage_tag(&self) -> Option<&str> {
+        self.usage_tag.as_deref()
+    }
+
+    pub(crate) fn model(&self) -> Option<&Model> {
+        self.model.as_ref()
+    }
+
+    pub(crate) fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub(crate) fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    pub(crate) fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    /// 校验请求参数，一次返回全部问题
+    /// glm为false时（OpenAI兼容服务）跳过GLM特有的取值范围、request_id格式和模型能力检查；stream为true时检查模型是否支持流式输出
+    pub(crate) fn validate(&self, glm: bool, stream: bool) -> Result<()> {
+        let mut issues = Vec::new();
+
+        if self.model.is_none() {
+            issues.push(ValidationIssue::MissingModel);
+        }
+        if self.messages.as_ref().is_none_or(|messages| messages.is_empty()) {
+            issues.push(ValidationIssue::MissingMessages);
+        }
+
+        // OpenAI的temperature取值为[0, 2]，top_p为[0, 1]
+        let temperature_range = if glm { 0.0..=1.0 } else { 0.0..=2.0 };
+        if let Some(temperature) = self.temperature {
+            if !temperature_range.contains(&temperature) {
+                issues.push(ValidationIssue::TemperatureOutOfRange(temperature));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            let valid = if glm { top_p > 0.0 && top_p < 1.0 } else { (0.0..=1.0).contains(&top_p) };
+            if !valid {
+                issues.push(ValidationIssue::TopPOutOfRange(top_p));
+            }
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            if max_tokens <= 0 {
+                issues.push(ValidationIssue::MaxTokensOutOfRange(max_tokens));
+            }
+        }
+        if self.do_sample == Some(false) && (self.temperature.is_some() || self.top_p.is_some()) {
+            issues.push(ValidationIssue::SamplingParamsWithoutSampling);
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > MAX_STOP_WORDS {
+                issues.push(ValidationIssue::TooManyStopWords(stop.len()));
+            }
+        }
+        if let Some(request_id) = self.request_id.as_ref().filter(|_| glm) {
+            if !is_valid_request_id(request_id) {
+                issues.push(ValidationIssue::InvalidRequestId(request_id.clone()));
+            }
+        }
+
+        let mut tool_call_ids = HashSet::new();
+        for (index, message) in self.messages.iter().flatten().enumerate() {
+            match message {
+                ChatMessage::ToolCall(tool_calls) => {
+                    tool_call_ids.extend(tool_calls.iter().map(|call| call.id.as_str()));
+                },
+                ChatMessage::Tool(tool_message) if !tool_call_ids.contains(tool_message.tool_call_id.as_str()) => {
+                    issues.push(ValidationIssue::OrphanToolMessage { index, tool_call_id: tool_message.tool_call_id.clone() });
+                },
+                _ => {},
+            }
+        }
+
+        let mut function_names = HashSet::new();
+        for tool in self.tools.iter().flatten() {
+            let Tool::Function(function) = tool else {
+                continue;
+            };
+            if !function_names.insert(function.name.as_str()) {
+                issues.push(ValidationIssue::DuplicateFunctionName(function.name.clone()));
+            }
+            if let Err(reason) = check_function_parameters(&function.parameters) {
+                issues.push(ValidationIssue::InvalidFunctionParameters { name: function.name.clone(), reason });
+            }
+        }
+
+        for key in self.extra_body.keys() {
+            if TYPED_FIELDS.contains(&key.as_str()) {
+                issues.push(ValidationIssue::ExtraBodyConflict(key.clone()));
+            }
+        }
+        for (name, value) in &self.headers {
+            if let Err(reason) = check_header(name, value) {
+                issues.push(ValidationIssue::InvalidHeader { name: name.clone(), reason });
+            }
+        }
+
+        if glm {
+            self.check_model(&mut issues, stream);
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(ValidationError { issues }))
+        }
+    }
+
+    // 检查请求内容是否超出所选模型的能力
+    fn check_model(&self, issues: &mut Vec<ValidationIssue>, stream: bool) {
+        let Some(model) = &self.model else {
+            return;
+        };
+
+        // 未收录的模型无法判断能力，交由服务端校验
+        let Some(capabilities) = model.capabilities() else {
+            return;
+        };
+
+        let mut unsupported = |feature| issues.push(ValidationIssue::UnsupportedByModel { model: model.to_string(), feature });
+        if stream && !capabilities.streaming {
+            unsupported("streaming");
+        }
+        if !capabilities.chat {
+            unsupported("chat completions");
+            return;
+        }
+        if !capabilities.vision && self.messages.iter().flatten().any(|message| matches!(message, ChatMessage::Image(_))) {
+            unsupported("image input");
+        }
+        if !capabilities.tools && self.tools.iter().flatten().any(|tool| matches!(tool, Tool::Function(_) | Tool::Retrieval(_))) {
+            unsupported("tools");
+        }
+        if !capabilities.web_search && self.tools.iter().flatten().any(|tool| matches!(tool, Tool::WebSearch(_))) {
+            unsupported("web search");
+        }
+        if self.max_tokens.is_some_and(|max_tokens| max_tokens.max(0) as usize > capabilities.max_output_tokens) {
+            unsupported("max_tokens this large");
         }
     }
 
-    pub(crate) fn is_requestable(&self) -> bool {
-        self.model.is_some() && self.messages.is_some() && !self.messages.as_ref().unwrap().is_empty()
+    /// 按设置的裁剪策略，将消息裁剪到模型上下文长度减去max_tokens以内
+    pub(crate) async fn truncate(&mut self) -> Result<()> {
+        let (Some(truncation), Some(model)) = (&self.truncation, &self.model) else {
+            return Ok(());
+        };
+
+        let budget = input_budget(model, self.max_tokens);
+        let messages = self.messages.take().unwrap_or_default();
+        self.messages = Some(truncation.truncate(messages, budget).await?);
+        Ok(())
     }
 
-    pub(crate) fn with_model(self, model: String) -> Self {
+    pub(crate) fn with_model(self, model: Model) -> Self {
         Self {
             model: Some(model),
             ..self
@@ -144,6 +372,68 @@ impl RequestInner {
             ..self
         }
     }
+
+    pub(crate) fn with_extra_body(self, key: String, value: serde_json::Value) -> Self {
+        let mut extra_body = self.extra_body;
+        extra_body.insert(key, value);
+
+        Self {
+            extra_body,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_header(self, name: String, value: String) -> Self {
+        let mut headers = self.headers;
+        headers.push((name, value));
+
+        Self {
+            headers,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_headers_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { headers: Some(timeout), ..self.timeouts },
+            ..self
+        }
+    }
+
+    pub(crate) fn with_deadline(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { deadline: Some(timeout), ..self.timeouts },
+            ..self
+        }
+    }
+
+    pub(crate) fn with_idle_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeouts: Timeouts { idle: Some(timeout), ..self.timeouts },
+            ..self
+        }
+    }
+
+    pub(crate) fn with_cancellation(self, token: CancellationToken) -> Self {
+        Self {
+            cancellation: Some(token),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_truncation(self, truncation: Arc<dyn TruncationStrategy>) -> Self {
+        Self {
+            truncation: Some(truncation),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_usage_tag(self, usage_tag: String) -> Self {
+        Self {
+            usage_tag: Some(usage_tag),
+            ..self
+        }
+    }
 }
 
 pub trait Unpack {
@@ -154,7 +444,7 @@ pub trait Unpack {
 }
 
 pub trait RequestBuild {
-    fn with_model(self, model: String) -> Self;
+    fn with_model(self, model: impl Into<Model>) -> Self;
     fn with_messages(self, messages: Vec<ChatMessage>) -> Self;
     fn add_message(self, message: ChatMessage) -> Self;
     fn with_request_id(self, request_id: String) -> Self;
@@ -167,12 +457,21 @@ pub trait RequestBuild {
     fn bind_retrieval(self, retrieval: Retrieval) -> Self;
     fn bind_web_search(self, web_search: WebSearch) -> Self;
     fn with_tool_choice(self, tool_choice: String) -> Self;
+    fn with_truncation(self, truncation: impl TruncationStrategy + 'static) -> Self;
+    fn with_extra_body(self, key: String, value: serde_json::Value) -> Self;
+    fn with_header(self, name: String, value: String) -> Self;
+    fn with_headers_timeout(self, timeout: Duration) -> Self;
+    fn with_deadline(self, timeout: Duration) -> Self;
+    fn with_idle_timeout(self, timeout: Duration) -> Self;
+    fn with_cancellation(self, token: CancellationToken) -> Self;
+    /// UsageTracker按该标签汇总用量和检查预算
+    fn with_usage_tag(self, usage_tag: String) -> Self;
 }
 
 impl <T: Unpack> RequestBuild for T {
-    fn with_model(self, model: String) -> Self {
+    fn with_model(self, model: impl Into<Model>) -> Self {
         let (inner, ext) = self.unpack();
-        Self::pack(inner.with_model(model), ext)
+        Self::pack(inner.with_model(model.into()), ext)
     }
 
     fn with_messages(self, messages: Vec<ChatMessage>) -> Self {
@@ -234,4 +533,44 @@ impl <T: Unpack> RequestBuild for T {
         let (inner, ext) = self.unpack();
         Self::pack(inner.with_tool_choice(tool_choice), ext)
     }
+
+    fn with_truncation(self, truncation: impl TruncationStrategy + 'static) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_truncation(Arc::new(truncation)), ext)
+    }
+
+    fn with_extra_body(self, key: String, value: serde_json::Value) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_extra_body(key, value), ext)
+    }
+
+    fn with_header(self, name: String, value: String) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_header(name, value), ext)
+    }
+
+    fn with_headers_timeout(self, timeout: Duration) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_headers_timeout(timeout), ext)
+    }
+
+    fn with_deadline(self, timeout: Duration) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_deadline(timeout), ext)
+    }
+
+    fn with_idle_timeout(self, timeout: Duration) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_idle_timeout(timeout), ext)
+    }
+
+    fn with_cancellation(self, token: CancellationToken) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_cancellation(token), ext)
+    }
+
+    fn with_usage_tag(self, usage_tag: String) -> Self {
+        let (inner, ext) = self.unpack();
+        Self::pack(inner.with_usage_tag(usage_tag), ext)
+    }
 } 
\ No newline at end of file
@@ -1,15 +1,17 @@
-use crate::chat::message::{ChatMessage, AssistantMessageDelta};
+use crate::chat::message::{ChatMessage, AssistantMessageDelta, WithExtra};
 
 #[derive(serde::Deserialize, Debug)]
 pub struct CompletionResult<T> {
     pub id: String,
+    #[serde(default)]
+    pub request_id: Option<String>,
     pub created: i64,
     pub model: String,
     pub choices: Vec<T>,
     pub usage: Option<Usage>,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Usage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
@@ -17,16 +19,161 @@ pub struct Usage {
 }
 
 #[derive(serde::Deserialize, Debug)]
+#[serde(from = "RawChoice")]
 pub struct CompletionChoice {
     pub index: i32,
-    pub finish_reason: String,
+    pub finish_reason: FinishReason,
     pub message: ChatMessage,
+    /// message中SDK未识别的字段
+    pub message_extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, serde::Deserialize)]
+#[serde(from = "RawChoiceDelta")]
 pub struct CompletionChoiceDelta {
     pub index: i32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub finish_reason: Option<String>,
+    pub finish_reason: Option<FinishReason>,
     pub delta: AssistantMessageDelta,
-}
\ No newline at end of file
+    /// delta中SDK未识别的字段
+    pub delta_extra: serde_json::Map<String, serde_json::Value>,
+}
+
+// 反序列化时先保留消息中的未识别字段，再拆到单独的字段中
+#[derive(serde::Deserialize)]
+struct RawChoice {
+    index: i32,
+    finish_reason: FinishReason,
+    message: WithExtra<ChatMessage>,
+}
+
+impl From<RawChoice> for CompletionChoice {
+    fn from(raw: RawChoice) -> Self {
+        Self { index: raw.index, finish_reason: raw.finish_reason, message: raw.message.value, message_extra: raw.message.extra }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct RawChoiceDelta {
+    index: i32,
+    finish_reason: Option<FinishReason>,
+    delta: WithExtra<AssistantMessageDelta>,
+}
+
+impl From<RawChoiceDelta> for CompletionChoiceDelta {
+    fn from(raw: RawChoiceDelta) -> Self {
+        Self { index: raw.index, finish_reason: raw.finish_reason, delta: raw.delta.value, delta_extra: raw.delta.extra }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishReason {
+    Stop,
+    ToolCalls,
+    Length,
+    Sensitive,
+    NetworkError,
+    Other(String),
+}
+
+impl FinishReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::Length => "length",
+            FinishReason::Sensitive => "sensitive",
+            FinishReason::NetworkError => "network_error",
+            FinishReason::Other(reason) => reason,
+        }
+    }
+
+    /// 输出因达到max_tokens或上下文长度上限而被截断
+    pub fn was_truncated(&self) -> bool {
+        matches!(self, FinishReason::Length)
+    }
+
+    /// 输出被内容安全审核拦截，包括OpenAI兼容服务返回的content_filter
+    pub fn was_filtered(&self) -> bool {
+        match self {
+            FinishReason::Sensitive => true,
+            FinishReason::Other(reason) => reason == "content_filter",
+            _ => false,
+        }
+    }
+
+    pub fn is_tool_calls(&self) -> bool {
+        matches!(self, FinishReason::ToolCalls)
+    }
+}
+
+impl From<&str> for FinishReason {
+    fn from(value: &str) -> Self {
+        match value {
+            "stop" => FinishReason::Stop,
+            "tool_calls" => FinishReason::ToolCalls,
+            "length" => FinishReason::Length,
+            "sensitive" => FinishReason::Sensitive,
+            "network_error" => FinishReason::NetworkError,
+            other => FinishReason::Other(other.to_string()),
+        }
+    }
+}
+
+impl serde::Serialize for FinishReason {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl <'de> serde::Deserialize<'de> for FinishReason {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let reason = String::deserialize(deserializer)?;
+        Ok(FinishReason::from(reason.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finish_reason() {
+        let reasons: Vec<FinishReason> = serde_json::from_str(r#"["stop", "tool_calls", "length", "sensitive", "content_filter", "network_error", "paused"]"#).unwrap();
+        assert_eq!(reasons, [
+            FinishReason::Stop,
+            FinishReason::ToolCalls,
+            FinishReason::Length,
+            FinishReason::Sensitive,
+            FinishReason::Other("content_filter".to_string()),
+            FinishReason::NetworkError,
+            FinishReason::Other("paused".to_string()),
+        ]);
+        // 未知的值原样写回
+        assert_eq!(serde_json::to_string(&reasons[6]).unwrap(), r#""paused""#);
+        assert_eq!(serde_json::to_string(&reasons[4]).unwrap(), r#""content_filter""#);
+
+        assert!(FinishReason::Length.was_truncated());
+        assert!(!FinishReason::Stop.was_truncated());
+        assert!(FinishReason::from("content_filter").was_filtered());
+        assert!(!FinishReason::Other("content_filter_v2".to_string()).was_filtered());
+        assert!(FinishReason::ToolCalls.is_tool_calls());
+        assert!(!FinishReason::Stop.is_tool_calls());
+    }
+
+    #[cfg(not(feature = "strict-deserialize"))]
+    #[test]
+    fn test_choice_keeps_unknown_message_fields() {
+        let choice: CompletionChoice = serde_json::from_value(serde_json::json!({
+            "index": 0, "finish_reason": "stop",
+            "message": {"role": "assistant", "content": "你好", "audio": {"id": "a1"}},
+        })).unwrap();
+        assert!(matches!(choice.message, ChatMessage::Assistant(ref content) if content == "你好"));
+        assert_eq!(choice.message_extra["audio"]["id"], "a1");
+    }
+}
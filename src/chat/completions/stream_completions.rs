@@ -1,33 +1,100 @@
+use bytes::{Bytes, BytesMut};
+use reqwest::{header::HeaderMap, StatusCode};
 use serde_json::json;
-use bytes::BytesMut;
 
-use crate::{authen::generate, error::{Error, Result}, send::Sendable};
+use std::time::{Duration, Instant};
 
-use super::{request_inner::RequestInner, result::{CompletionChoiceDelta, CompletionResult}, Unpack};
+use crate::{error::{Error, Result}, http::{post_completions, Exchange, HttpResponse}, key_pool::KeyLease, limiter::RateLimitPermit, openglm::OpenGLM, response::{Response, ResponseMeta}, send::Sendable};
+use crate::authen::Auth;
+use crate::cache::{lookup, to_event_stream, ResponseCache};
+use crate::cancel::{with_cancel, CancellationToken};
+use crate::fallback::with_fallback;
+use crate::telemetry::RequestSpan;
+use crate::usage::UsageScope;
+use crate::chat::message::{AssistantMessageDelta, ChatMessage};
+use crate::timeout::{with_timeout, TimeoutPhase};
+
+use super::{request_inner::RequestInner, result::{CompletionChoiceDelta, CompletionResult, FinishReason, Usage}, Unpack};
 
 pub struct StreamCompletionsRequest {
-    api_key: String,
+    client: OpenGLM,
     inner: RequestInner,
 }
 
 impl StreamCompletionsRequest {
-    pub(crate) fn new_with(api_key: String, inner: RequestInner) -> Self {
+    pub(crate) fn new_with(client: OpenGLM, inner: RequestInner) -> Self {
         Self {
-            api_key,
+            client,
             inner,
         }
     }
+
+    /// 与send相同，但同时返回状态码、响应头和收到响应头的耗时
+    pub async fn send_with_response(self) -> Result<Response<CompletionDeltaIter>> {
+        let span = RequestSpan::start(&self.client, &self.inner, true);
+        match span.instrument(self.send_cached()).await {
+            Ok(mut response) => {
+                span.record_meta(&response.meta);
+                response.value.span = span;
+                Ok(response)
+            },
+            Err(e) => {
+                span.record_error(&e);
+                Err(e)
+            },
+        }
+    }
+
+    async fn send_cached(self) -> Result<Response<CompletionDeltaIter>> {
+        let cached = lookup(&self.client, &self.inner)?;
+        if let Some((_, result)) = cached.as_ref().and_then(|(cache, key)| cache.get_result(key)) {
+            // 将缓存的完整结果重放为流
+            let response = HttpResponse::from_bytes(StatusCode::OK, HeaderMap::new(), to_event_stream(&result)?);
+            let mut iter = CompletionDeltaIter::new(self.client, &self.inner, response, None, None, None);
+            iter.replay = true;
+            return Ok(Response {
+                meta: ResponseMeta::cached(self.inner.model().cloned()),
+                body: Bytes::new(),
+                value: iter,
+            });
+        }
+
+        let mut response = with_fallback(self.client, self.inner, send_once).await?;
+        // 降级后的结果来自其他模型，不缓存在所请求模型的键下
+        response.value.cache = cached.filter(|_| response.meta.fallbacks.is_empty());
+        Ok(response)
+    }
+}
+
+// 只有在收到响应头之前失败才会降级，已开始输出的流不会切换模型
+async fn send_once(client: OpenGLM, mut inner: RequestInner, deadline: Option<Instant>) -> Result<Response<CompletionDeltaIter>> {
+    inner.validate(client.auth() == Auth::Jwt, true)?;
+    inner.truncate().await?;
+    let usage = UsageScope::new(&client, &inner);
+    if let Some(usage) = &usage {
+        usage.check_budgets(&inner)?;
+    }
+
+    let Exchange { response, meta, deadline, permit, lease } = post_completions(&client, &inner, true, deadline).await?;
+    let mut iter = CompletionDeltaIter::new(client, &inner, response, deadline, permit, lease);
+    iter.usage = usage.map(|usage| usage.with_lease(iter.lease.as_ref()));
+
+    Ok(Response {
+        meta,
+        body: Bytes::new(),
+        value: iter,
+    })
 }
 
 impl Unpack for StreamCompletionsRequest {
-    type ExtType = String;
+    type ExtType = OpenGLM;
 
     fn unpack(self) -> (RequestInner, Self::ExtType) {
-        (self.inner, self.api_key)
+        (self.inner, self.client)
     }
 
     fn pack(inner: RequestInner, ext: Self::ExtType) -> Self {
-        Self { api_key: ext, inner }
+        Self { client: ext, inner }
     }
 }
 
@@ -35,45 +102,98 @@ impl Sendable for StreamCompletionsRequest {
     type Output = CompletionDeltaIter;
 
     async fn send(self) -> Result<Self::Output> {
-        if !self.inner.is_requestable() {
-            return Err(Error::MissingParams);
-        }
-
-        let token = generate(&self.api_key)?;
-        let Ok(mut body) = serde_json::to_value(&self.inner) else {
-            return Err(Error::InvalidApiKey);
-        };
-
-        body.as_object_mut().unwrap().insert("stream".to_string(), json!(true));
-
-        let response = reqwest::Client::new()
-            .post("https://open.bigmodel.cn/api/paas/v4/chat/completions")
-            .header("Authorization", format!("Bearer {}", &token))
-            .json(&body)
-            .send()
-            .await?;
-
-        Ok(CompletionDeltaIter{
-            response,
-            bytes: BytesMut::new(),
-            read_eof: false,
-        })
+        Ok(self.send_with_response().await?.value)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStatus {
+    Streaming,
+    /// 收到了[DONE]
+    Finished,
+    /// 被CancellationToken取消，连接已断开
+    Cancelled,
+}
+
 pub struct CompletionDeltaIter {
-    response: reqwest::Response,
+    response: Option<HttpResponse>,
     bytes: BytesMut,
     read_eof: bool,
+    last_line: Option<String>,
+    idle: Option<Duration>,
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    // 第一个choice已收到的全部片段
+    accumulated: Vec<AssistantMessageDelta>,
+    status: StreamStatus,
+    client: OpenGLM,
+    // 流结束后才释放限流许可和密钥
+    permit: Option<RateLimitPermit>,
+    lease: Option<KeyLease>,
+    // 正常结束后将第一个choice写入缓存
+    cache: Option<(ResponseCache, String)>,
+    summary: StreamSummary,
+    // 流结束时才记录耗时，随迭代器一同释放
+    span: RequestSpan,
+    usage: Option<UsageScope>,
+    // 重放缓存的结果，没有发出请求，不计入限流和用量
+    replay: bool,
+}
+
+// 写入缓存和记录用量时需要的片段信息
+#[derive(Default)]
+struct StreamSummary {
+    id: String,
+    created: i64,
+    model: String,
+    finish_reason: Option<FinishReason>,
+    usage: Option<Usage>,
 }
 
 impl CompletionDeltaIter {
+    fn new(client: OpenGLM, inner: &RequestInner, response: HttpResponse, deadline: Option<Instant>, permit: Option<RateLimitPermit>, lease: Option<KeyLease>) -> Self {
+        Self {
+            response: Some(response),
+            bytes: BytesMut::new(),
+            read_eof: false,
+            last_line: None,
+            idle: inner.timeouts().or(client.timeouts()).idle,
+            deadline,
+            cancellation: inner.cancellation().cloned(),
+            accumulated: Vec::new(),
+            status: StreamStatus::Streaming,
+            client,
+            permit,
+            lease,
+            cache: None,
+            summary: StreamSummary::default(),
+            span: RequestSpan::none(),
+            usage: None,
+            replay: false,
+        }
+    }
+
+    /// 读取下一个片段，流结束或被取消时返回None，可通过status区分
     pub async fn next(&mut self) -> Result<Option<CompletionResult<CompletionChoiceDelta>>> {
-        loop {
-            if !self.read_eof {
-                self.read_eof = self.read_chunk().await?.is_none();
-            }
+        if self.status != StreamStatus::Streaming {
+            return Ok(None);
+        }
 
+        let result = self.read_next().await;
+        match &result {
+            Ok(Some(result)) => self.span.record_chunk(result),
+            Ok(None) => self.span.record_stream_end(self.partial_message().ok().as_ref()),
+            Err(e) => {
+                self.record_usage();
+                self.span.record_error(e);
+            },
+        }
+        result
+    }
+
+    async fn read_next(&mut self) -> Result<Option<CompletionResult<CompletionChoiceDelta>>> {
+
+        loop {
             let newline_pos = self.bytes.iter().position(|&item| item == b'\n');
             // 如果找到了换行符
             if let Some(pos) = newline_pos {
@@ -85,27 +205,128 @@ impl CompletionDeltaIter {
                     continue;
                 }
 
-                println!("line:{} end", line);
-                
-                return to_result(line);
-            } else {
-                if self.read_eof {
-                    let line = self.bytes.split();
-                    let line = String::from_utf8_lossy(&line);
-                    let line = line.trim();
-                    if line.is_empty() {
-                        return Err(Error::StreamError);
-                    }
-
-                    println!("line:{} end", line);
-                    return to_result(line);
+                self.last_line = Some(line.to_string());
+                return self.accumulate(to_result(line)?);
+            }
+
+            if self.read_eof {
+                let line = self.bytes.split();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim();
+                if line.is_empty() {
+                    return Err(Error::StreamError);
                 }
+
+                self.last_line = Some(line.to_string());
+                return self.accumulate(to_result(line)?);
+            }
+
+            let cancellation = self.cancellation.clone();
+            let Some(chunk) = with_cancel(self.read_chunk(), cancellation.as_ref()).await else {
+                // 丢弃响应以断开连接
+                self.record_usage();
+                self.response = None;
+                self.permit = None;
+                self.lease = None;
+                self.status = StreamStatus::Cancelled;
+                return Ok(None);
+            };
+            self.read_eof = chunk?.is_none();
+        }
+    }
+
+    fn accumulate(&mut self, result: Option<CompletionResult<CompletionChoiceDelta>>) -> Result<Option<CompletionResult<CompletionChoiceDelta>>> {
+        let Some(result) = result else {
+            self.store();
+            self.record_usage();
+            self.response = None;
+            self.permit = None;
+            self.lease = None;
+            self.status = StreamStatus::Finished;
+            return Ok(None);
+        };
+
+        // 部分服务在每个片段中都返回累计的usage，只保留最后一次
+        if result.usage.is_some() {
+            self.summary.usage = result.usage.clone();
+        }
+
+        if let Some(choice) = result.choices.iter().find(|choice| choice.index == 0) {
+            self.accumulated.push(choice.delta.clone());
+            if choice.finish_reason.is_some() {
+                self.summary.finish_reason = choice.finish_reason.clone();
             }
         }
+        if self.cache.is_some() {
+            self.summary.id.clone_from(&result.id);
+            self.summary.created = result.created;
+            self.summary.model.clone_from(&result.model);
+        }
+        Ok(Some(result))
+    }
+
+    // 流结束、被取消或出错时记录最后收到的usage，只记录一次
+    fn record_usage(&mut self) {
+        let Some(usage) = self.summary.usage.take().filter(|_| !self.replay) else {
+            return;
+        };
+
+        if let Some(rate_limiter) = self.client.rate_limiter() {
+            rate_limiter.record_usage(usage.total_tokens.max(0) as u64);
+        }
+        if let Some(lease) = &self.lease {
+            lease.record_usage(&usage);
+        }
+        if let Some(scope) = &self.usage {
+            scope.record(&usage);
+        }
+    }
+
+    fn store(&mut self) {
+        let Some((cache, key)) = self.cache.take() else {
+            return;
+        };
+        let (Some(finish_reason), Ok(message)) = (&self.summary.finish_reason, self.partial_message()) else {
+            return;
+        };
+
+        let body = json!({
+            "id": self.summary.id,
+            "created": self.summary.created,
+            "model": self.summary.model,
+            "choices": [{"index": 0, "finish_reason": finish_reason, "message": message}],
+            "usage": self.summary.usage,
+        });
+        if let Ok(body) = serde_json::to_vec(&body) {
+            cache.put(&key, Bytes::from(body));
+        }
+    }
+
+    pub fn status(&self) -> StreamStatus {
+        self.status
+    }
+
+    /// 第一个choice目前已收到的全部片段
+    pub fn accumulated(&self) -> &[AssistantMessageDelta] {
+        &self.accumulated
+    }
+
+    /// 将已收到的片段合并为一条消息，被取消时可用于保存已生成的内容
+    pub fn partial_message(&self) -> Result<ChatMessage> {
+        ChatMessage::try_from(self.accumulated.clone())
+    }
+
+    /// 最近一次读取到的原始SSE数据行
+    pub fn last_line(&self) -> Option<&str> {
+        self.last_line.as_deref()
     }
 
     async fn read_chunk(&mut self) -> Result<Option<()>> {
-        let Some(chunk) = self.response.chunk().await? else {
+        let Some(response) = self.response.as_mut() else {
+            return Ok(None);
+        };
+        let chunk = with_timeout(response.body.chunk(), self.idle, self.deadline, TimeoutPhase::Idle).await??;
+        let Some(chunk) = chunk else {
             return Ok(None);
         };
 
@@ -124,5 +345,5 @@ fn to_result(line: &str) -> Result<Option<CompletionResult<CompletionChoiceDelta
     }
 
     // 移除已读取的行和换行符
-    return Ok(Some(serde_json::from_str::<CompletionResult<CompletionChoiceDelta>>(line)?));
+    Ok(Some(serde_json::from_str::<CompletionResult<CompletionChoiceDelta>>(line)?))
 }
\ No newline at end of file
@@ -0,0 +1,154 @@
+use std::marker::PhantomData;
+
+use crate::chat::message::ChatMessage;
+use crate::error::Result;
+use crate::model::Model;
+use crate::openglm::OpenGLM;
+use crate::send::Sendable;
+
+use super::completions::CompletionsRequestBuilder;
+use super::request_inner::RequestInner;
+use super::result::{CompletionChoice, CompletionResult};
+use super::stream_completions::StreamCompletionsRequest;
+use super::Unpack;
+
+pub struct NoModel;
+pub struct HasModel;
+pub struct NoMessages;
+pub struct HasMessages;
+
+/// 在类型上记录是否已设置model和messages，两者都设置后才能调用send()和stream()。
+///
+/// 其余参数仍通过RequestBuild设置。
+///
+/// ```no_run
+/// # use openglm::prelude::*;
+/// # async fn run(client: OpenGLM) -> Result<()> {
+/// let result = client.chat().completions().create_typed()
+///     .with_model(Model::Glm4Flash)
+///     .add_message(ChatMessage::User("你好".to_string()))
+///     .send()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// 未设置model时无法调用send()：
+///
+/// ```compile_fail,E0599
+/// # use openglm::prelude::*;
+/// # async fn run(client: OpenGLM) -> Result<()> {
+/// let result = client.chat().completions().create_typed()
+///     .add_message(ChatMessage::User("你好".to_string()))
+///     .send()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// 未设置messages时无法调用stream()：
+///
+/// ```compile_fail,E0599
+/// # use openglm::prelude::*;
+/// # async fn run(client: OpenGLM) -> Result<()> {
+/// let iter = client.chat().completions().create_typed()
+///     .with_model(Model::Glm4Flash)
+///     .stream()
+///     .send()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TypedCompletionsRequestBuilder<M, Msg> {
+    client: OpenGLM,
+    inner: RequestInner,
+    _state: PhantomData<(M, Msg)>,
+}
+
+impl TypedCompletionsRequestBuilder<NoModel, NoMessages> {
+    pub(crate) fn new(client: OpenGLM) -> Self {
+        Self::pack(RequestInner::new(), client)
+    }
+}
+
+impl <M, Msg> TypedCompletionsRequestBuilder<M, Msg> {
+    fn into_state<M2, Msg2>(self) -> TypedCompletionsRequestBuilder<M2, Msg2> {
+        TypedCompletionsRequestBuilder {
+            client: self.client,
+            inner: self.inner,
+            _state: PhantomData,
+        }
+    }
+
+    pub fn with_model(self, model: impl Into<Model>) -> TypedCompletionsRequestBuilder<HasModel, Msg> {
+        let builder = self.into_state();
+        TypedCompletionsRequestBuilder {
+            inner: builder.inner.with_model(model.into()),
+            ..builder
+        }
+    }
+
+    pub fn with_messages(self, messages: Vec<ChatMessage>) -> TypedCompletionsRequestBuilder<M, HasMessages> {
+        let builder = self.into_state();
+        TypedCompletionsRequestBuilder {
+            inner: builder.inner.with_messages(messages),
+            ..builder
+        }
+    }
+
+    pub fn add_message(self, message: ChatMessage) -> TypedCompletionsRequestBuilder<M, HasMessages> {
+        let builder = self.into_state();
+        TypedCompletionsRequestBuilder {
+            inner: builder.inner.add_message(message),
+            ..builder
+        }
+    }
+
+    /// 转换为不检查参数的普通构建器
+    pub fn into_dynamic(self) -> CompletionsRequestBuilder {
+        CompletionsRequestBuilder::pack(self.inner, self.client)
+    }
+}
+
+impl TypedCompletionsRequestBuilder<HasModel, HasMessages> {
+    pub fn stream(self) -> StreamCompletionsRequest {
+        self.into_dynamic().stream()
+    }
+}
+
+impl <M, Msg> Unpack for TypedCompletionsRequestBuilder<M, Msg> {
+    type ExtType = OpenGLM;
+
+    fn unpack(self) -> (RequestInner, Self::ExtType) {
+        (self.inner, self.client)
+    }
+
+    fn pack(inner: RequestInner, ext: Self::ExtType) -> Self {
+        Self { client: ext, inner, _state: PhantomData }
+    }
+}
+
+impl Sendable for TypedCompletionsRequestBuilder<HasModel, HasMessages> {
+    type Output = CompletionResult<CompletionChoice>;
+
+    async fn send(self) -> Result<Self::Output> {
+        self.into_dynamic().send().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::completions::RequestBuild;
+
+    #[test]
+    fn test_state_transitions() {
+        let builder = TypedCompletionsRequestBuilder::new(OpenGLM::new("id.secret".to_string()))
+            .with_temperature(0.5)
+            .add_message(ChatMessage::User("你好".to_string()))
+            .with_model(Model::Glm4Flash)
+            .with_max_tokens(128);
+
+        let _: TypedCompletionsRequestBuilder<HasModel, HasMessages> = builder;
+    }
+}
@@ -0,0 +1,210 @@
+use std::{error::Error as StdError, fmt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    MissingModel,
+    MissingMessages,
+    /// temperature取值范围为[0.0, 1.0]
+    TemperatureOutOfRange(f32),
+    /// top_p取值范围为(0.0, 1.0)
+    TopPOutOfRange(f32),
+    MaxTokensOutOfRange(i32),
+    /// do_sample为false时temperature和top_p不生效
+    SamplingParamsWithoutSampling,
+    TooManyStopWords(usize),
+    /// 工具消息之前没有对应id的工具调用
+    OrphanToolMessage { index: usize, tool_call_id: String },
+    DuplicateFunctionName(String),
+    InvalidFunctionParameters { name: String, reason: String },
+    /// request_id只能由字母、数字、下划线和连字符组成，长度6到64
+    InvalidRequestId(String),
+    UnsupportedByModel { model: String, feature: &'static str },
+    /// with_extra_body设置的字段与类型化参数重名
+    ExtraBodyConflict(String),
+    InvalidHeader { name: String, reason: String },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::MissingModel => write!(f, "model is required"),
+            ValidationIssue::MissingMessages => write!(f, "at least one message is required"),
+            ValidationIssue::TemperatureOutOfRange(value) => write!(f, "temperature {} is out of range [0.0, 1.0]", value),
+            ValidationIssue::TopPOutOfRange(value) => write!(f, "top_p {} is out of range (0.0, 1.0)", value),
+            ValidationIssue::MaxTokensOutOfRange(value) => write!(f, "max_tokens {} must be positive", value),
+            ValidationIssue::SamplingParamsWithoutSampling => write!(f, "temperature and top_p are ignored when do_sample is false"),
+            ValidationIssue::TooManyStopWords(count) => write!(f, "stop supports at most {} words, got {}", MAX_STOP_WORDS, count),
+            ValidationIssue::OrphanToolMessage { index, tool_call_id } => write!(f, "tool message {} refers to unknown tool call {}", index, tool_call_id),
+            ValidationIssue::DuplicateFunctionName(name) => write!(f, "function {} is bound more than once", name),
+            ValidationIssue::InvalidFunctionParameters { name, reason } => write!(f, "function {} has invalid parameters: {}", name, reason),
+            ValidationIssue::InvalidRequestId(request_id) => write!(f, "request_id {:?} is invalid", request_id),
+            ValidationIssue::UnsupportedByModel { model, feature } => write!(f, "{} does not support {}", model, feature),
+            ValidationIssue::ExtraBodyConflict(key) => write!(f, "extra body field {} conflicts with a typed parameter", key),
+            ValidationIssue::InvalidHeader { name, reason } => write!(f, "header {} is invalid: {}", name, reason),
+        }
+    }
+}
+
+pub(crate) const MAX_STOP_WORDS: usize = 4;
+
+/// 发送前本地校验发现的全部问题
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationError {
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+}
+
+impl StdError for ValidationError {}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn is_valid_request_id(request_id: &str) -> bool {
+    (6..=64).contains(&request_id.len())
+        && request_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub(crate) fn check_header(name: &str, value: &str) -> Result<(), String> {
+    let name = reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+    if name == reqwest::header::AUTHORIZATION || name == reqwest::header::CONTENT_TYPE {
+        return Err("header is set by the SDK".to_string());
+    }
+    reqwest::header::HeaderValue::from_str(value).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// 检查函数参数是否为合法的JSON Schema对象定义
+pub(crate) fn check_function_parameters(parameters: &serde_json::Value) -> Result<(), String> {
+    let Some(schema) = parameters.as_object() else {
+        return Err("parameters must be a JSON object".to_string());
+    };
+
+    match schema.get("type") {
+        Some(serde_json::Value::String(ty)) if ty == "object" => {},
+        _ => return Err("type must be \"object\"".to_string()),
+    }
+
+    let properties = match schema.get("properties") {
+        None => None,
+        Some(serde_json::Value::Object(properties)) => Some(properties),
+        Some(_) => return Err("properties must be an object".to_string()),
+    };
+
+    for (name, property) in properties.into_iter().flatten() {
+        if !property.is_object() {
+            return Err(format!("property {} must be an object", name));
+        }
+    }
+
+    match schema.get("required") {
+        None => {},
+        Some(serde_json::Value::Array(required)) => {
+            for name in required {
+                let Some(name) = name.as_str() else {
+                    return Err("required must be an array of strings".to_string());
+                };
+                if !properties.is_some_and(|properties| properties.contains_key(name)) {
+                    return Err(format!("required property {} is not defined", name));
+                }
+            }
+        },
+        Some(_) => return Err("required must be an array".to_string()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{completions::request_inner::RequestInner, message::{ChatMessage, ImageMessage, ToolMessage}, tools::FunctionTool};
+    use crate::error::Error;
+
+    #[test]
+    fn test_collects_all_issues() {
+        let inner = RequestInner::new()
+            .with_model("glm-4v".into())
+            .add_message(ChatMessage::Tool(ToolMessage { content: "晴".to_string(), tool_call_id: "call_1".to_string() }))
+            .with_temperature(1.5)
+            .with_do_sample(false)
+            .with_request_id("id".to_string())
+            .bind_function(FunctionTool { name: "f".to_string(), description: String::new(), parameters: serde_json::json!({"type": "object"}) })
+            .bind_function(FunctionTool { name: "f".to_string(), description: String::new(), parameters: serde_json::json!({"type": "string"}) });
+
+        let Err(Error::Validation(e)) = inner.validate(true, false) else {
+            panic!("expected validation error");
+        };
+        assert_eq!(e.issues(), &[
+            ValidationIssue::TemperatureOutOfRange(1.5),
+            ValidationIssue::SamplingParamsWithoutSampling,
+            ValidationIssue::InvalidRequestId("id".to_string()),
+            ValidationIssue::OrphanToolMessage { index: 0, tool_call_id: "call_1".to_string() },
+            ValidationIssue::DuplicateFunctionName("f".to_string()),
+            ValidationIssue::InvalidFunctionParameters { name: "f".to_string(), reason: "type must be \"object\"".to_string() },
+            ValidationIssue::UnsupportedByModel { model: "glm-4v".to_string(), feature: "tools" },
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_model_not_restricted() {
+        let inner = RequestInner::new()
+            .with_model("glm-5v".into())
+            .add_message(ChatMessage::Image(vec![ImageMessage::ImageUrl("https://example.com/a.png".to_string())]))
+            .with_max_tokens(16_384)
+            .bind_function(FunctionTool { name: "f".to_string(), description: String::new(), parameters: serde_json::json!({"type": "object"}) });
+        assert!(inner.validate(true, true).is_ok());
+    }
+
+    #[test]
+    fn test_streaming_unsupported() {
+        let inner = RequestInner::new()
+            .with_model("cogview-3".into())
+            .add_message(ChatMessage::User("画一只猫".to_string()));
+
+        let Err(Error::Validation(e)) = inner.validate(true, true) else {
+            panic!("expected validation error");
+        };
+        assert_eq!(e.issues()[0], ValidationIssue::UnsupportedByModel { model: "cogview-3".to_string(), feature: "streaming" });
+        let Err(Error::Validation(e)) = inner.validate(true, false) else {
+            panic!("expected validation error");
+        };
+        assert!(!e.issues().iter().any(|issue| matches!(issue, ValidationIssue::UnsupportedByModel { feature: "streaming", .. })));
+    }
+
+    #[test]
+    fn test_extra_body() {
+        let inner = RequestInner::new()
+            .with_model("glm-4".into())
+            .add_message(ChatMessage::User("你好".to_string()))
+            .with_extra_body("user_id".to_string(), serde_json::json!("u1"))
+            .with_header("X-Trace-Id".to_string(), "abc".to_string());
+        assert!(inner.validate(true, false).is_ok());
+
+        let body = inner.to_body(true).unwrap();
+        assert_eq!(body["user_id"], "u1");
+        assert_eq!(body["stream"], true);
+
+        let inner = inner
+            .with_extra_body("temperature".to_string(), serde_json::json!(0.1))
+            .with_header("Authorization".to_string(), "Bearer x".to_string());
+        let Err(Error::Validation(e)) = inner.validate(true, false) else {
+            panic!("expected validation error");
+        };
+        assert_eq!(e.issues().len(), 2);
+        assert_eq!(e.issues()[0], ValidationIssue::ExtraBodyConflict("temperature".to_string()));
+    }
+}
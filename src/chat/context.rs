@@ -0,0 +1,299 @@
+use crate::error::{Error, Result};
+use crate::model::Model;
+use crate::openglm::OpenGLM;
+use crate::send::{BoxFuture, Sendable};
+
+use super::completions::completions::CompletionsRequestBuilder;
+use super::completions::RequestBuild;
+use super::message::{ChatMessage, ImageMessage};
+
+// 每条消息的role、分隔符等额外开销
+const MESSAGE_OVERHEAD: usize = 4;
+// 未指定max_tokens时为输出预留的token数
+const DEFAULT_OUTPUT_RESERVE: usize = 1024;
+// 每张图片按固定token数估算
+const IMAGE_TOKENS: usize = 1024;
+const SUMMARY_PROMPT: &str = "请简要总结以下对话的要点，保留后续对话需要的事实和结论。";
+
+/// 粗略估算文本的token数：中文约每1.5个字一个token，其他字符约每4个一个token。
+pub fn estimate_tokens(text: &str) -> usize {
+    let (cjk, other) = text.chars().fold((0usize, 0usize), |(cjk, other), c| {
+        if is_cjk(c) { (cjk + 1, other) } else { (cjk, other + 1) }
+    });
+
+    (cjk * 2).div_ceil(3) + other.div_ceil(4)
+}
+
+// 从末尾保留不超过budget个token的文本
+fn keep_tail(text: &str, budget: usize) -> &str {
+    let (mut cjk, mut other) = (0usize, 0usize);
+    for (pos, c) in text.char_indices().rev() {
+        if is_cjk(c) { cjk += 1 } else { other += 1 }
+        if (cjk * 2).div_ceil(3) + other.div_ceil(4) > budget {
+            return &text[pos + c.len_utf8()..];
+        }
+    }
+    text
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3000..=0x303F | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0xFF00..=0xFFEF)
+}
+
+pub fn estimate_message_tokens(message: &ChatMessage) -> usize {
+    let content = match message {
+        ChatMessage::System(content) | ChatMessage::User(content) | ChatMessage::Assistant(content) => estimate_tokens(content),
+        ChatMessage::Image(images) => images.iter().map(|image| match image {
+            ImageMessage::Text(text) => estimate_tokens(text),
+            ImageMessage::ImageUrl(_) => IMAGE_TOKENS,
+        }).sum(),
+        ChatMessage::ToolCall(tool_calls) => tool_calls.iter()
+            .map(|call| estimate_tokens(&call.function.name) + estimate_tokens(&call.function.arguments.to_string()))
+            .sum(),
+        ChatMessage::Tool(tool_message) => estimate_tokens(&tool_message.content),
+    };
+
+    content + MESSAGE_OVERHEAD
+}
+
+pub fn estimate_messages_tokens(messages: &[ChatMessage]) -> usize {
+    messages.iter().map(estimate_message_tokens).sum()
+}
+
+/// 留给输入消息的token数
+pub fn input_budget(model: &Model, max_tokens: Option<i32>) -> usize {
+    let reserve = max_tokens.map_or(DEFAULT_OUTPUT_RESERVE.min(model.max_output_tokens()), |max_tokens| max_tokens.max(0) as usize);
+    model.context_length().saturating_sub(reserve)
+}
+
+/// 发送前裁剪消息列表，使其估算token数不超过budget。
+///
+/// 内置策略都不会拆开工具调用和对应的工具结果。
+pub trait TruncationStrategy: Send + Sync {
+    fn truncate(&self, messages: Vec<ChatMessage>, budget: usize) -> BoxFuture<'_, Result<Vec<ChatMessage>>>;
+}
+
+// 不可拆分的消息组：系统消息单独固定，工具调用与紧随其后的工具结果为一组
+struct Segment {
+    messages: Vec<ChatMessage>,
+    tokens: usize,
+    pinned: bool,
+}
+
+fn split_segments(messages: Vec<ChatMessage>) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for message in messages {
+        let tokens = estimate_message_tokens(&message);
+        if let (ChatMessage::Tool(_), Some(last)) = (&message, segments.last_mut()) {
+            if matches!(last.messages.first(), Some(ChatMessage::ToolCall(_))) {
+                last.tokens += tokens;
+                last.messages.push(message);
+                continue;
+            }
+        }
+
+        let pinned = matches!(message, ChatMessage::System(_));
+        segments.push(Segment { messages: vec![message], tokens, pinned });
+    }
+    segments
+}
+
+fn flatten(segments: Vec<Segment>) -> Vec<ChatMessage> {
+    segments.into_iter().flat_map(|segment| segment.messages).collect()
+}
+
+fn flatten_ref(segments: &[Segment]) -> Vec<&ChatMessage> {
+    segments.iter().flat_map(|segment| &segment.messages).collect()
+}
+
+// 从最早的未固定消息组开始丢弃，但始终保留最后一组
+fn drop_oldest(mut segments: Vec<Segment>, budget: usize) -> Vec<Segment> {
+    let mut total: usize = segments.iter().map(|segment| segment.tokens).sum();
+    while total > budget {
+        let droppable = segments.iter().filter(|segment| !segment.pinned).count();
+        if droppable <= 1 {
+            break;
+        }
+
+        let Some(pos) = segments.iter().position(|segment| !segment.pinned) else {
+            break;
+        };
+        total -= segments.remove(pos).tokens;
+    }
+    segments
+}
+
+/// 丢弃最早的消息，保留系统消息
+pub struct DropOldest;
+
+impl TruncationStrategy for DropOldest {
+    fn truncate(&self, messages: Vec<ChatMessage>, budget: usize) -> BoxFuture<'_, Result<Vec<ChatMessage>>> {
+        Box::pin(async move {
+            Ok(flatten(drop_oldest(split_segments(messages), budget)))
+        })
+    }
+}
+
+/// 只保留系统消息和最后n组消息，仍超出时继续丢弃最早的消息
+pub struct KeepLastN(pub usize);
+
+impl TruncationStrategy for KeepLastN {
+    fn truncate(&self, messages: Vec<ChatMessage>, budget: usize) -> BoxFuture<'_, Result<Vec<ChatMessage>>> {
+        Box::pin(async move {
+            let mut segments = split_segments(messages);
+            let mut remaining = segments.iter().filter(|segment| !segment.pinned).count();
+            segments.retain(|segment| {
+                if segment.pinned || remaining <= self.0 {
+                    return true;
+                }
+                remaining -= 1;
+                false
+            });
+
+            Ok(flatten(drop_oldest(segments, budget)))
+        })
+    }
+}
+
+/// 调用模型将较早的对话总结为一条系统消息，保留最后keep_last组消息
+pub struct Summarize {
+    client: OpenGLM,
+    model: Model,
+    keep_last: usize,
+}
+
+impl Summarize {
+    pub fn new(client: &OpenGLM, model: impl Into<Model>, keep_last: usize) -> Self {
+        Self {
+            client: client.clone(),
+            model: model.into(),
+            keep_last,
+        }
+    }
+
+    // 总结请求本身也受上下文长度限制：只总结放得下的最近部分，更早的消息直接丢弃，单条过长时保留其末尾
+    fn transcript(&self, mut segments: Vec<Segment>) -> Result<String> {
+        let budget = input_budget(&self.model, None).saturating_sub(estimate_tokens(SUMMARY_PROMPT) + 2 * MESSAGE_OVERHEAD);
+        loop {
+            let transcript = serde_json::to_string(&flatten_ref(&segments))?;
+            if estimate_tokens(&transcript) <= budget {
+                return Ok(transcript);
+            }
+            if segments.len() <= 1 {
+                return Ok(keep_tail(&transcript, budget).to_string());
+            }
+            segments.remove(0);
+        }
+    }
+
+    async fn summarize(&self, segments: Vec<Segment>) -> Result<String> {
+        let transcript = self.transcript(segments)?;
+        let result = CompletionsRequestBuilder::new(self.client.clone())
+            .with_model(self.model.clone())
+            .add_message(ChatMessage::System(SUMMARY_PROMPT.to_string()))
+            .add_message(ChatMessage::User(transcript))
+            .send()
+            .await?;
+
+        match result.choices.into_iter().next().map(|choice| choice.message) {
+            Some(ChatMessage::Assistant(summary)) => Ok(summary),
+            _ => Err(Error::EmptyDeltaList),
+        }
+    }
+}
+
+impl TruncationStrategy for Summarize {
+    fn truncate(&self, messages: Vec<ChatMessage>, budget: usize) -> BoxFuture<'_, Result<Vec<ChatMessage>>> {
+        Box::pin(async move {
+            if estimate_messages_tokens(&messages) <= budget {
+                return Ok(messages);
+            }
+
+            let segments = split_segments(messages);
+            let (pinned, rest): (Vec<Segment>, Vec<Segment>) = segments.into_iter().partition(|segment| segment.pinned);
+            if rest.len() <= self.keep_last {
+                return Ok(flatten(drop_oldest(pinned.into_iter().chain(rest).collect(), budget)));
+            }
+
+            let split = rest.len() - self.keep_last;
+            let mut rest = rest;
+            let recent = rest.split_off(split);
+            let summary = self.summarize(rest).await?;
+
+            let mut messages = flatten(pinned);
+            messages.push(ChatMessage::System(format!("以下是之前对话的摘要：\n{}", summary)));
+            messages.extend(flatten(recent));
+
+            Ok(flatten(drop_oldest(split_segments(messages), budget)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::message::{Function, ToolCall, ToolMessage};
+
+    fn tool_round() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::ToolCall(vec![ToolCall {
+                id: "call_1".to_string(),
+                ty: "function".to_string(),
+                function: Function { name: "get_weather".to_string(), arguments: serde_json::json!({"city": "北京"}) },
+            }]),
+            ChatMessage::Tool(ToolMessage { content: "晴".to_string(), tool_call_id: "call_1".to_string() }),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_system_and_tool_pairs() {
+        let mut messages = vec![ChatMessage::System("你是一个助手".to_string())];
+        messages.push(ChatMessage::User("很长的问题".repeat(100)));
+        messages.extend(tool_round());
+        messages.push(ChatMessage::User("今天天气怎么样".to_string()));
+
+        let budget = estimate_messages_tokens(&messages) - 1;
+        let truncated = DropOldest.truncate(messages, budget).await.unwrap();
+        assert_eq!(truncated.len(), 4);
+        assert!(matches!(truncated[0], ChatMessage::System(_)));
+        assert!(matches!(truncated[1], ChatMessage::ToolCall(_)));
+        assert!(matches!(truncated[2], ChatMessage::Tool(_)));
+
+        let truncated = KeepLastN(1).truncate(truncated, usize::MAX).await.unwrap();
+        assert_eq!(truncated.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_summarize() {
+        use std::sync::Arc;
+        use crate::transport::{MockResponse, MockTransport};
+
+        let transport = Arc::new(MockTransport::new().with_response(MockResponse::json(&serde_json::json!({
+            "id": "1", "created": 1711433468, "model": "charglm-3",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "用户问过天气"}}],
+        }))));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(transport.clone());
+        let messages = vec![
+            ChatMessage::System("你是一个助手".to_string()),
+            ChatMessage::User("最早的问题".to_string()),
+            ChatMessage::User("很长的问题".repeat(3000)),
+            ChatMessage::Assistant("回答".to_string()),
+            ChatMessage::User("最后的问题".to_string()),
+        ];
+
+        let truncated = Summarize::new(&client, Model::CharGlm3, 1).truncate(messages, 1000).await.unwrap();
+        assert_eq!(truncated.len(), 3);
+        assert!(matches!(&truncated[0], ChatMessage::System(content) if content == "你是一个助手"));
+        assert!(matches!(&truncated[1], ChatMessage::System(content) if content.ends_with("用户问过天气")));
+        assert!(matches!(&truncated[2], ChatMessage::User(content) if content == "最后的问题"));
+
+        // 被总结的历史超出总结模型的上下文长度时，只保留放得下的最近部分
+        let body: serde_json::Value = serde_json::from_slice(&transport.requests()[0].body).unwrap();
+        assert_eq!(body["model"], "charglm-3");
+        assert_eq!(body["messages"][0]["content"], SUMMARY_PROMPT);
+        let transcript = body["messages"][1]["content"].as_str().unwrap();
+        assert!(!transcript.contains("最早的问题"));
+        assert!(transcript.contains("回答"));
+        assert!(estimate_tokens(transcript) <= input_budget(&Model::CharGlm3, None));
+    }
+}
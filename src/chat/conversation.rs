@@ -0,0 +1,352 @@
+use crate::error::{Error, Result};
+use crate::model::Model;
+use crate::openglm::OpenGLM;
+use crate::send::Sendable;
+
+use super::completions::completions::CompletionsRequestBuilder;
+use super::completions::result::{CompletionChoice, CompletionChoiceDelta, CompletionResult};
+use super::completions::stream_completions::{CompletionDeltaIter, StreamStatus};
+use super::completions::RequestBuild;
+use super::message::{AssistantMessageDelta, ChatMessage};
+
+/// 多轮对话：保存系统提示词和历史消息，发送时自动追加用户输入和模型回复。
+///
+/// 可通过serde保存为记录文件，之后加载继续对话。
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(try_from = "Transcript")]
+pub struct Conversation {
+    model: Model,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(default)]
+    messages: Vec<ChatMessage>,
+    // 每一轮开始时messages的长度，用于撤销
+    #[serde(default)]
+    turns: Vec<usize>,
+}
+
+// 反序列化时先读入Transcript，校验turns后再转换，避免手动修改或截断的记录文件在undo时越界
+#[derive(serde::Deserialize)]
+struct Transcript {
+    model: Model,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default)]
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    turns: Vec<usize>,
+}
+
+impl TryFrom<Transcript> for Conversation {
+    type Error = String;
+
+    fn try_from(transcript: Transcript) -> std::result::Result<Self, Self::Error> {
+        let increasing = transcript.turns.windows(2).all(|pair| pair[0] < pair[1]);
+        let in_bounds = transcript.turns.last().is_none_or(|&last| last < transcript.messages.len());
+        if !increasing || !in_bounds {
+            return Err(format!("invalid turns {:?} for {} messages", transcript.turns, transcript.messages.len()));
+        }
+
+        Ok(Self {
+            model: transcript.model,
+            system: transcript.system,
+            messages: transcript.messages,
+            turns: transcript.turns,
+        })
+    }
+}
+
+impl Conversation {
+    pub fn new(model: impl Into<Model>) -> Self {
+        Self {
+            model: model.into(),
+            system: None,
+            messages: Vec::new(),
+            turns: Vec::new(),
+        }
+    }
+
+    pub fn with_system(self, system: String) -> Self {
+        Self {
+            system: Some(system),
+            ..self
+        }
+    }
+
+    /// 切换后续请求使用的模型，历史消息保持不变
+    pub fn with_model(self, model: impl Into<Model>) -> Self {
+        Self {
+            model: model.into(),
+            ..self
+        }
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    /// 历史消息，不包含系统提示词
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn turn_count(&self) -> usize {
+        self.turns.len()
+    }
+
+    /// 开始新的一轮对话
+    pub fn push_user(&mut self, message: ChatMessage) {
+        self.turns.push(self.messages.len());
+        self.messages.push(message);
+    }
+
+    /// 追加不开启新一轮的消息，如模型回复或工具调用结果
+    pub fn push(&mut self, message: ChatMessage) {
+        self.messages.push(message);
+    }
+
+    /// 将非流式请求的第一个回复追加到历史中
+    pub fn record(&mut self, result: &CompletionResult<CompletionChoice>) -> Result<()> {
+        let Some(choice) = result.choices.first() else {
+            return Err(Error::EmptyDeltaList);
+        };
+
+        self.messages.push(choice.message.clone());
+        Ok(())
+    }
+
+    /// 将流式请求收到的全部片段合并后追加到历史中
+    pub fn record_deltas(&mut self, deltas: Vec<AssistantMessageDelta>) -> Result<()> {
+        self.messages.push(ChatMessage::try_from(deltas)?);
+        Ok(())
+    }
+
+    /// 撤销最后一轮对话，返回被移除的消息
+    pub fn undo(&mut self) -> Option<Vec<ChatMessage>> {
+        let start = self.turns.pop()?;
+        Some(self.messages.split_off(start))
+    }
+
+    /// 复制当前对话，之后两者的历史互不影响
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    /// 生成包含系统提示词和全部历史的请求
+    pub fn request(&self, client: &OpenGLM) -> CompletionsRequestBuilder {
+        let mut messages = Vec::with_capacity(self.messages.len() + 1);
+        if let Some(system) = &self.system {
+            messages.push(ChatMessage::System(system.clone()));
+        }
+        messages.extend(self.messages.iter().cloned());
+
+        client.chat().completions().create()
+            .with_model(self.model.clone())
+            .with_messages(messages)
+    }
+
+    /// 发送一轮对话，失败时撤销本轮的用户输入
+    pub async fn send(&mut self, client: &OpenGLM, input: ChatMessage) -> Result<CompletionResult<CompletionChoice>> {
+        self.push_user(input);
+
+        let result = match self.request(client).send().await {
+            Ok(result) => result,
+            Err(e) => {
+                self.undo();
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = self.record(&result) {
+            self.undo();
+            return Err(e);
+        }
+
+        Ok(result)
+    }
+
+    /// 以流式方式发送一轮对话，读取完毕后自动追加模型回复
+    pub async fn stream(&mut self, client: &OpenGLM, input: ChatMessage) -> Result<ConversationStream<'_>> {
+        self.push_user(input);
+
+        match self.request(client).stream().send().await {
+            Ok(iter) => Ok(ConversationStream {
+                conversation: self,
+                iter,
+                finished: false,
+            }),
+            Err(e) => {
+                self.undo();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// 流结束前被丢弃时按取消处理：已生成的内容追加到历史中，没有可用的内容则撤销本轮输入
+pub struct ConversationStream<'a> {
+    conversation: &'a mut Conversation,
+    iter: CompletionDeltaIter,
+    // 本轮已写入历史或已撤销，之后的调用不再修改历史
+    finished: bool,
+}
+
+impl ConversationStream<'_> {
+    /// 读取下一个片段；流结束或被取消时，将已生成的内容追加到历史中。
+    ///
+    /// 读取出错，或被取消时尚未生成可用的内容，则撤销本轮输入。
+    pub async fn next(&mut self) -> Result<Option<CompletionResult<CompletionChoiceDelta>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let result = match self.iter.next().await {
+            Ok(Some(result)) => return Ok(Some(result)),
+            Ok(None) => self.finish(self.iter.status() == StreamStatus::Cancelled),
+            Err(e) => Err(e),
+        };
+        self.finished = true;
+        if result.is_err() {
+            self.conversation.undo();
+        }
+        result.map(|_| None)
+    }
+
+    // partial为true表示流未读完（被取消或被丢弃）
+    fn finish(&mut self, partial: bool) -> Result<()> {
+        let deltas = self.iter.accumulated().to_vec();
+        if deltas.is_empty() && partial {
+            self.conversation.undo();
+            return Ok(());
+        }
+
+        // 未读完时可能只收到了部分工具调用，无法合并为消息
+        match self.conversation.record_deltas(deltas) {
+            Err(_) if partial => {
+                self.conversation.undo();
+                Ok(())
+            },
+            result => result,
+        }
+    }
+
+    pub fn status(&self) -> StreamStatus {
+        self.iter.status()
+    }
+}
+
+impl Drop for ConversationStream<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            let _ = self.finish(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_and_fork() {
+        let mut conversation = Conversation::new("glm-4".to_string())
+            .with_system("你是一个乐于助人的助手".to_string());
+        conversation.push_user(ChatMessage::User("你好".to_string()));
+        conversation.push(ChatMessage::Assistant("你好！".to_string()));
+
+        let mut forked = conversation.fork();
+        forked.push_user(ChatMessage::User("讲个笑话".to_string()));
+        forked.push(ChatMessage::Assistant("……".to_string()));
+        assert_eq!(forked.messages().len(), 4);
+        assert_eq!(conversation.messages().len(), 2);
+
+        let removed = forked.undo().unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(forked.turn_count(), 1);
+        assert_eq!(forked.messages().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stream_records_once() {
+        use reqwest::StatusCode;
+        use crate::transport::{MockResponse, MockTransport};
+
+        let chunk = |content: &str| serde_json::json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "delta": {"role": "assistant", "content": content}}],
+        });
+        let transport = MockTransport::new()
+            .with_response(MockResponse::sse([chunk("你好！")]))
+            .with_response(MockResponse::chunked(StatusCode::OK, [format!("data: {}\n\nbroken\n\n", chunk("从前"))]));
+        let client = OpenGLM::new("mockid.mocksecret".to_string()).with_transport(transport);
+        let mut conversation = Conversation::new("glm-4".to_string());
+
+        let mut stream = conversation.stream(&client, ChatMessage::User("你好".to_string())).await.unwrap();
+        while stream.next().await.unwrap().is_some() {}
+        // 结束后再次调用不会重复追加回复
+        assert!(stream.next().await.unwrap().is_none());
+        drop(stream);
+        assert_eq!(conversation.messages().len(), 2);
+
+        // 读取出错时撤销本轮输入，之前的对话保持不变
+        let mut stream = conversation.stream(&client, ChatMessage::User("讲个故事".to_string())).await.unwrap();
+        assert!(stream.next().await.unwrap().is_some());
+        assert!(stream.next().await.is_err());
+        assert!(stream.next().await.unwrap().is_none());
+        drop(stream);
+        assert_eq!(conversation.messages().len(), 2);
+        assert_eq!(conversation.turn_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_dropped() {
+        use crate::transport::{MockResponse, MockTransport};
+
+        let chunk = |content: &str, finish_reason: Option<&str>| serde_json::json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": finish_reason, "delta": {"role": "assistant", "content": content}}],
+        });
+        let events = || MockResponse::sse([chunk("从前", None), chunk("有座山", Some("stop"))]);
+        let transport = MockTransport::new().with_response(events()).with_response(events());
+        let client = OpenGLM::new("mockid.mocksecret".to_string()).with_transport(transport);
+        let mut conversation = Conversation::new("glm-4".to_string());
+
+        // 未读取任何内容就丢弃，撤销本轮输入
+        drop(conversation.stream(&client, ChatMessage::User("讲个故事".to_string())).await.unwrap());
+        assert!(conversation.messages().is_empty());
+        assert_eq!(conversation.turn_count(), 0);
+
+        // 读到一半丢弃，保留已生成的内容，下一轮不会出现连续两条用户消息
+        let mut stream = conversation.stream(&client, ChatMessage::User("讲个故事".to_string())).await.unwrap();
+        assert!(stream.next().await.unwrap().is_some());
+        drop(stream);
+        assert_eq!(conversation.turn_count(), 1);
+        assert!(matches!(&conversation.messages()[1], ChatMessage::Assistant(content) if content == "从前"));
+    }
+
+    #[test]
+    fn test_transcript_roundtrip() {
+        let mut conversation = Conversation::new("glm-4".to_string())
+            .with_system("你是一个乐于助人的助手".to_string());
+        conversation.push_user(ChatMessage::User("你好".to_string()));
+        conversation.push(ChatMessage::Assistant("你好！".to_string()));
+
+        let transcript = serde_json::to_string(&conversation).unwrap();
+        let mut loaded: Conversation = serde_json::from_str(&transcript).unwrap();
+        assert_eq!(loaded.model(), &Model::Glm4);
+        assert_eq!(loaded.system(), conversation.system());
+        assert_eq!(loaded.messages().len(), 2);
+        assert_eq!(loaded.undo().unwrap().len(), 2);
+
+        let mut transcript: serde_json::Value = serde_json::from_str(&transcript).unwrap();
+        transcript["turns"] = serde_json::json!([0, 2]);
+        assert!(serde_json::from_value::<Conversation>(transcript.clone()).is_err());
+        transcript["turns"] = serde_json::json!([1, 0]);
+        assert!(serde_json::from_value::<Conversation>(transcript).is_err());
+    }
+}
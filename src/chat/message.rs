@@ -1,14 +1,55 @@
+use std::{collections::BTreeMap, ops::Deref};
+
 use serde::ser::SerializeMap;
 
 use crate::error::Error;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+/// 反序列化得到的值，以及服务端返回但SDK尚未识别的字段。
+#[derive(Debug)]
+pub struct WithExtra<T> {
+    pub value: T,
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl <T> WithExtra<T> {
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl <T> Deref for WithExtra<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+// 未识别的字段默认保留在extra中，开启strict-deserialize特性时直接报错。
+fn collect_unknown_field<'de, A>(
+    map: &mut A,
+    key: String,
+    expected: &'static [&'static str],
+    extra: &mut serde_json::Map<String, serde_json::Value>,
+) -> Result<(), A::Error>
+where
+    A: serde::de::MapAccess<'de>,
+{
+    if cfg!(feature = "strict-deserialize") {
+        return Err(serde::de::Error::unknown_field(&key, expected));
+    }
+
+    extra.insert(key, map.next_value()?);
+    Ok(())
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Function {
     pub name: String,
     pub arguments: serde_json::Value,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct ToolCall {
     pub id: String,
     #[serde(rename = "type")]
@@ -16,13 +57,119 @@ pub struct ToolCall {
     pub function: Function,
 }
 
-#[derive(Debug)]
+/// 流式返回的工具调用片段：id和name通常在首个片段，arguments分多次返回，按index归并。
+#[derive(serde::Deserialize, Debug, Default, Clone)]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    pub index: Option<i32>,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(rename = "type", default)]
+    pub ty: Option<String>,
+    #[serde(default)]
+    pub function: Option<FunctionDelta>,
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone)]
+pub struct FunctionDelta {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "arguments_fragment")]
+    pub arguments: Option<String>,
+}
+
+// 参数片段一般是字符串，也兼容直接返回完整JSON对象的情况
+fn arguments_fragment<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value: Option<serde_json::Value> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(match value {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(fragment)) => Some(fragment),
+        Some(value) => Some(value.to_string()),
+    })
+}
+
+#[derive(Default)]
+struct PartialToolCall {
+    id: Option<String>,
+    ty: Option<String>,
+    name: Option<String>,
+    arguments: String,
+}
+
+/// 将流式返回的工具调用片段按index合并为完整的ToolCall。
+#[derive(Default)]
+pub struct ToolCallMerger {
+    calls: BTreeMap<i32, PartialToolCall>,
+}
+
+impl ToolCallMerger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, delta: ToolCallDelta) {
+        // 没有index时视为新的调用
+        let index = delta.index.unwrap_or_else(|| self.calls.keys().next_back().map_or(0, |last| last + 1));
+        let call = self.calls.entry(index).or_default();
+
+        if let Some(id) = delta.id {
+            call.id = Some(id);
+        }
+        if let Some(ty) = delta.ty {
+            call.ty = Some(ty);
+        }
+        if let Some(function) = delta.function {
+            // 与id相同，name总是完整的，部分服务会在后续片段中重复发送
+            if let Some(name) = function.name.filter(|name| !name.is_empty()) {
+                call.name = Some(name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.arguments.push_str(&arguments);
+            }
+        }
+    }
+
+    pub fn extend(&mut self, deltas: impl IntoIterator<Item = ToolCallDelta>) {
+        for delta in deltas {
+            self.push(delta);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    pub fn finish(self) -> Result<Vec<ToolCall>, Error> {
+        self.calls.into_iter().map(|(index, call)| {
+            let (Some(id), Some(name)) = (call.id, call.name) else {
+                return Err(Error::IncompleteToolCall(index));
+            };
+
+            let arguments = if call.arguments.trim().is_empty() {
+                serde_json::Value::Object(serde_json::Map::new())
+            } else {
+                serde_json::from_str(&call.arguments)?
+            };
+
+            Ok(ToolCall {
+                id,
+                ty: call.ty.unwrap_or_else(|| "function".to_string()),
+                function: Function { name, arguments },
+            })
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ToolMessage {
     pub content: String,
     pub tool_call_id: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ImageMessage {
     Text(String),
     ImageUrl(String),
@@ -47,7 +194,7 @@ impl serde::Serialize for ImageMessage {
             },
             ImageMessage::ImageUrl(url) => {
                 map.serialize_entry("type", "image_url")?;
-                map.serialize_entry("image_url", &ImageUrlWrapper { url: url })?;
+                map.serialize_entry("image_url", &ImageUrlWrapper { url })?;
             },
         }
         map.end()
@@ -55,7 +202,7 @@ impl serde::Serialize for ImageMessage {
 
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ChatMessage {
     System(String),
     User(String),
@@ -104,6 +251,15 @@ impl serde::Serialize for ChatMessage {
 }
 
 impl <'de> serde::Deserialize<'de> for ChatMessage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        WithExtra::<ChatMessage>::deserialize(deserializer).map(WithExtra::into_inner)
+    }
+}
+
+impl <'de> serde::Deserialize<'de> for WithExtra<ChatMessage> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
@@ -111,7 +267,7 @@ impl <'de> serde::Deserialize<'de> for ChatMessage {
         struct MessageVisitor;
 
         impl<'de> serde::de::Visitor<'de> for MessageVisitor {
-            type Value = ChatMessage;
+            type Value = WithExtra<ChatMessage>;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
                 formatter.write_str("chat message")
@@ -125,20 +281,21 @@ impl <'de> serde::Deserialize<'de> for ChatMessage {
                 let mut content: Option<serde_json::Value> = None;
                 let mut tool_calls: Option<Vec<ToolCall>> = None;
                 let mut tool_call_id: Option<String> = None;
+                let mut extra = serde_json::Map::new();
 
-                while let Some(key) = map.next_key()? {
-                    match key {
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
                         "role" => role = map.next_value()?,
                         "content" => content = map.next_value()?,
                         "tool_calls" => tool_calls = map.next_value()?,
                         "tool_call_id" => tool_call_id = map.next_value()?,
-                        _ => return Err(serde::de::Error::unknown_field(key, &["role", "content", "tool_calls", "tool_call_id"])),
+                        _ => collect_unknown_field(&mut map, key, &["role", "content", "tool_calls", "tool_call_id"], &mut extra)?,
                     }
                 }
 
                 let role: String = role.ok_or_else(|| serde::de::Error::missing_field("role"))?;
 
-                match (role.as_str(), content, tool_calls, tool_call_id) {
+                let value = match (role.as_str(), content, tool_calls, tool_call_id) {
                     ("system", Some(serde_json::Value::String(content)), None, None) => Ok(ChatMessage::System(content)),
                     ("user", Some(serde_json::Value::String(content)), None, None) => Ok(ChatMessage::User(content)),
                     ("user", Some(serde_json::Value::Array(content)), None, None) => {
@@ -172,7 +329,9 @@ impl <'de> serde::Deserialize<'de> for ChatMessage {
                     ("assistant", None, Some(tool_calls), None) => Ok(ChatMessage::ToolCall(tool_calls)),
                     ("tool", Some(serde_json::Value::String(content)), None, Some(tool_call_id)) => Ok(ChatMessage::Tool(ToolMessage { content, tool_call_id })),
                     _ => Err(serde::de::Error::custom("invalid message")),
-                }
+                }?;
+
+                Ok(WithExtra { value, extra })
             }
         }
 
@@ -180,13 +339,22 @@ impl <'de> serde::Deserialize<'de> for ChatMessage {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AssistantMessageDelta {
     Content(String),
-    ToolCall(Vec<ToolCall>),
+    ToolCall(Vec<ToolCallDelta>),
 }
 
 impl <'de> serde::Deserialize<'de> for AssistantMessageDelta {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de> 
+    {
+        WithExtra::<AssistantMessageDelta>::deserialize(deserializer).map(WithExtra::into_inner)
+    }
+}
+
+impl <'de> serde::Deserialize<'de> for WithExtra<AssistantMessageDelta> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de> 
@@ -194,7 +362,7 @@ impl <'de> serde::Deserialize<'de> for AssistantMessageDelta {
         struct DeltaVisitor;
 
         impl<'de> serde::de::Visitor<'de> for DeltaVisitor {
-            type Value = AssistantMessageDelta;
+            type Value = WithExtra<AssistantMessageDelta>;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
                 formatter.write_str("assistant message delta")
@@ -204,26 +372,28 @@ impl <'de> serde::Deserialize<'de> for AssistantMessageDelta {
             where
                 A: serde::de::MapAccess<'de>, 
             {
-                let mut role = None;
-                let mut content = None;
-                let mut tool_calls: Option<Vec<ToolCall>> = None;
+                let mut role: Option<String> = None;
+                let mut content: Option<String> = None;
+                let mut tool_calls: Option<Vec<ToolCallDelta>> = None;
+                let mut extra = serde_json::Map::new();
 
-                while let Some(key) = map.next_key()? {
-                    match key {
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
                         "role" => role = map.next_value()?,
                         "content" => content = map.next_value()?,
                         "tool_calls" => tool_calls = map.next_value()?,
-                        _ => return Err(serde::de::Error::unknown_field(key, &["role", "content", "tool_calls"])),
+                        _ => collect_unknown_field(&mut map, key, &["role", "content", "tool_calls"], &mut extra)?,
                     }
                 }
 
-                let role: String = role.ok_or_else(|| serde::de::Error::missing_field("role"))?;
-
-                match (role.as_str(), content, tool_calls) {
-                    ("assistant", Some(content), None) => Ok(AssistantMessageDelta::Content(content)),
-                    ("assistant", None, Some(tool_calls)) => Ok(AssistantMessageDelta::ToolCall(tool_calls)),
+                // 只有首个片段会带上role
+                let value = match (role.as_deref().unwrap_or("assistant"), content, tool_calls) {
+                    ("assistant", content, Some(tool_calls)) if content.as_deref().unwrap_or_default().is_empty() => Ok(AssistantMessageDelta::ToolCall(tool_calls)),
+                    ("assistant", content, None) => Ok(AssistantMessageDelta::Content(content.unwrap_or_default())),
                     _ => Err(serde::de::Error::custom("invalid message")),
-                }
+                }?;
+
+                Ok(WithExtra { value, extra })
             }
         }
 
@@ -235,34 +405,77 @@ impl TryFrom<Vec<AssistantMessageDelta>> for ChatMessage {
     type Error = Error;
     
     fn try_from(value: Vec<AssistantMessageDelta>) -> Result<Self, Self::Error> {
-        let mut message = None;
+        let mut content: Option<String> = None;
+        let mut merger: Option<ToolCallMerger> = None;
         for delta in value {
             match delta {
                 AssistantMessageDelta::Content(income) => {
-                    match message {
-                        Some(ChatMessage::Assistant(ref mut content)) => {
-                            content.push_str(&income);
-                        },
-                        None => {
-                            message = Some(ChatMessage::Assistant(income));
-                        },
-                        _ => return Err(Error::Conflict),
+                    if merger.is_some() {
+                        if income.is_empty() {
+                            continue;
+                        }
+                        return Err(Error::Conflict);
                     }
+                    content.get_or_insert_with(String::new).push_str(&income);
                 },
-                AssistantMessageDelta::ToolCall(mut income) => {
-                    match message {
-                        Some(ChatMessage::ToolCall(ref mut tool_calls)) => {
-                            tool_calls.append(&mut income);
-                        },
-                        None => {
-                            message = Some(ChatMessage::ToolCall(income));
-                        },
-                        _ => return Err(Error::Conflict),
+                AssistantMessageDelta::ToolCall(income) => {
+                    if content.as_deref().is_some_and(|content| !content.is_empty()) {
+                        return Err(Error::Conflict);
                     }
+                    merger.get_or_insert_with(ToolCallMerger::new).extend(income);
                 }
             }
         }
 
-        message.ok_or(Error::EmptyDeltaList)
+        match (content, merger) {
+            (_, Some(merger)) => Ok(ChatMessage::ToolCall(merger.finish()?)),
+            (Some(content), None) => Ok(ChatMessage::Assistant(content)),
+            (None, None) => Err(Error::EmptyDeltaList),
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "strict-deserialize"))]
+    #[test]
+    fn test_unknown_fields_preserved() {
+        let json = r#"{"role":"assistant","content":"你好","audio":{"id":"a1"}}"#;
+        let message: WithExtra<ChatMessage> = serde_json::from_str(json).unwrap();
+        assert!(matches!(message.value, ChatMessage::Assistant(ref content) if content == "你好"));
+        assert_eq!(message.extra["audio"]["id"], "a1");
+
+        let delta: AssistantMessageDelta = serde_json::from_str(r#"{"role":"assistant","content":"你","seq":1}"#).unwrap();
+        assert!(matches!(delta, AssistantMessageDelta::Content(ref content) if content == "你"));
+    }
+
+    #[test]
+    fn test_merge_tool_call_deltas() {
+        let chunks = [
+            r#"{"role":"assistant","tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]}"#,
+            r#"{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]}"#,
+            r#"{"tool_calls":[{"index":1,"id":"call_2","type":"function","function":{"name":"get_time","arguments":"{}"}}]}"#,
+            // 重复发送完整的name
+            r#"{"tool_calls":[{"index":0,"function":{"name":"get_weather","arguments":"\"北京\"}"}}]}"#,
+        ];
+        let deltas: Vec<AssistantMessageDelta> = chunks.iter().map(|chunk| serde_json::from_str(chunk).unwrap()).collect();
+
+        let ChatMessage::ToolCall(tool_calls) = ChatMessage::try_from(deltas).unwrap() else {
+            panic!("expected tool calls");
+        };
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments["city"], "北京");
+        assert_eq!(tool_calls[1].function.name, "get_time");
+    }
+
+    #[cfg(feature = "strict-deserialize")]
+    #[test]
+    fn test_unknown_fields_rejected() {
+        let json = r#"{"role":"assistant","content":"你好","audio":{"id":"a1"}}"#;
+        assert!(serde_json::from_str::<ChatMessage>(json).is_err());
+    }
+}
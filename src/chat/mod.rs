@@ -1,4 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod chat;
 pub mod completions;
+pub mod context;
+pub mod conversation;
 pub mod message;
 pub mod tools;
\ No newline at end of file
@@ -1,25 +1,26 @@
 use serde::ser::SerializeMap;
 
-#[derive(serde::Serialize, Default)]
+#[derive(serde::Serialize, Default, Clone)]
 pub struct FunctionTool {
     pub name: String,
     pub description: String,
     pub parameters: serde_json::Value,
 }
 
-#[derive(serde::Serialize, Default)]
+#[derive(serde::Serialize, Default, Clone)]
 pub struct Retrieval {
     pub knowledge_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prompt_template: Option<String>,
 }
 
-#[derive(serde::Serialize, Default)]
+#[derive(serde::Serialize, Default, Clone)]
 pub struct WebSearch {
     pub search_query: String,
     pub enable: bool,
 }
 
+#[derive(Clone)]
 pub enum Tool {
     Function(FunctionTool),
     Retrieval(Retrieval),
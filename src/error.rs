@@ -1,5 +1,8 @@
 use std::{error::Error as StdError, fmt};
 
+use crate::chat::completions::validation::ValidationError;
+use crate::timeout::TimeoutPhase;
+
 #[derive(Debug)]
 pub enum Error{
     MissingParams,
@@ -7,6 +10,21 @@ pub enum Error{
     StreamError,
     EmptyDeltaList,
     Conflict,
+    IncompleteToolCall(i32),
+    Validation(ValidationError),
+    /// 服务端返回的非2xx响应
+    Api { status: u16, code: Option<String>, message: String },
+    /// 响应体无法解析，body为原始内容
+    Decode { body: String, source: serde_json::Error },
+    Timeout { phase: TimeoutPhase },
+    /// 请求在收到响应前被取消；流式请求被取消时不返回错误
+    Cancelled,
+    /// HttpTransport返回的其他错误
+    Transport(String),
+    /// 已花费的费用超出UsageTracker的硬预算，请求未发送
+    BudgetExceeded { spent: f64, limit: f64 },
+    /// 客户端设置的取值无效，如限流速率为0
+    InvalidConfig(String),
     Reqwest(reqwest::Error),
     SerdeError(serde_json::Error),
 }
@@ -16,6 +34,8 @@ impl StdError for Error {
         match self {
             Error::Reqwest(e) => Some(e),
             Error::SerdeError(e) => Some(e),
+            Error::Validation(e) => Some(e),
+            Error::Decode { source, .. } => Some(source),
             _ => None,
         }
     }
@@ -30,6 +50,15 @@ impl fmt::Display for Error {
             Error::StreamError => write!(f, "StreamError"),
             Error::EmptyDeltaList => write!(f, "EmptyDeltaList"),
             Error::Conflict => write!(f, "Conflict"),
+            Error::IncompleteToolCall(index) => write!(f, "IncompleteToolCall: {}", index),
+            Error::Validation(e) => write!(f, "Validation: {}", e),
+            Error::Api { status, code, message } => write!(f, "Api: {} {} {}", status, code.as_deref().unwrap_or("-"), message),
+            Error::Decode { source, .. } => write!(f, "Decode: {}", source),
+            Error::Timeout { phase } => write!(f, "Timeout: {}", phase),
+            Error::Cancelled => write!(f, "Cancelled"),
+            Error::Transport(message) => write!(f, "Transport: {}", message),
+            Error::BudgetExceeded { spent, limit } => write!(f, "BudgetExceeded: {} / {}", spent, limit),
+            Error::InvalidConfig(message) => write!(f, "InvalidConfig: {}", message),
             Error::SerdeError(e) => write!(f, "SerdeError: {}", e),
             Error::Reqwest(e) => write!(f, "Reqwest: {}", e),
         }
@@ -48,4 +77,10 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Self {
+        Error::Validation(e)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::{future::Future, time::Instant};
+
+use crate::chat::completions::request_inner::RequestInner;
+use crate::error::{Error, Result};
+use crate::model::Model;
+use crate::openglm::OpenGLM;
+use crate::response::Response;
+use crate::telemetry::attempt_failed;
+
+// 平台返回的“Prompt超长”错误码
+const CONTEXT_LENGTH_CODE: &str = "1261";
+
+/// 触发降级的失败类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FallbackTrigger {
+    /// 429
+    RateLimit,
+    /// 5xx以及连接失败
+    ServerError,
+    /// 收到响应前超时
+    Timeout,
+    /// 输入超出模型的上下文长度
+    ContextLength,
+}
+
+impl FallbackTrigger {
+    const ALL: [FallbackTrigger; 4] = [
+        FallbackTrigger::RateLimit,
+        FallbackTrigger::ServerError,
+        FallbackTrigger::Timeout,
+        FallbackTrigger::ContextLength,
+    ];
+
+    fn matches(&self, error: &Error) -> bool {
+        match (self, error) {
+            (FallbackTrigger::RateLimit, Error::Api { status, .. }) => *status == 429,
+            (FallbackTrigger::ServerError, Error::Api { status, .. }) => *status >= 500,
+            (FallbackTrigger::ServerError, Error::Reqwest(_) | Error::Transport(_)) => true,
+            (FallbackTrigger::Timeout, Error::Timeout { .. }) => true,
+            (FallbackTrigger::ContextLength, Error::Api { code, message, .. }) => {
+                code.as_deref() == Some(CONTEXT_LENGTH_CODE) || message.contains("context length")
+            },
+            _ => false,
+        }
+    }
+}
+
+/// 降级前失败的一次尝试
+#[derive(Debug, Clone)]
+pub struct FallbackAttempt {
+    pub model: Model,
+    pub error: String,
+}
+
+#[derive(Clone)]
+struct FallbackTarget {
+    model: Model,
+    // 为空时使用发起请求的客户端
+    client: Option<OpenGLM>,
+}
+
+/// 降级策略：按顺序排列的候选模型，以及触发降级的失败类型。
+///
+/// 请求的模型在列表中时，从它之后的候选开始尝试，否则依次尝试全部候选。
+#[derive(Clone)]
+pub struct FallbackPolicy {
+    targets: Vec<FallbackTarget>,
+    triggers: Vec<FallbackTrigger>,
+}
+
+impl FallbackPolicy {
+    /// 默认在全部FallbackTrigger发生时降级
+    pub fn new<M: Into<Model>>(models: impl IntoIterator<Item = M>) -> Self {
+        Self {
+            targets: models.into_iter().map(|model| FallbackTarget { model: model.into(), client: None }).collect(),
+            triggers: FallbackTrigger::ALL.to_vec(),
+        }
+    }
+
+    /// 追加通过其他接口地址或密钥访问的候选
+    pub fn with_target(mut self, model: impl Into<Model>, client: OpenGLM) -> Self {
+        self.targets.push(FallbackTarget { model: model.into(), client: Some(client) });
+        self
+    }
+
+    /// 只在指定的失败类型发生时降级
+    pub fn with_triggers(self, triggers: impl IntoIterator<Item = FallbackTrigger>) -> Self {
+        Self {
+            triggers: triggers.into_iter().collect(),
+            ..self
+        }
+    }
+
+    fn should_fallback(&self, error: &Error) -> bool {
+        self.triggers.iter().any(|trigger| trigger.matches(error))
+    }
+
+    fn candidates(&self, model: &Model) -> &[FallbackTarget] {
+        match self.targets.iter().position(|target| target.client.is_none() && &target.model == model) {
+            Some(position) => &self.targets[position + 1..],
+            None => &self.targets,
+        }
+    }
+}
+
+/// 按客户端的降级策略发送请求，attempt负责单次发送；响应中记录实际应答的模型和失败的尝试
+pub(crate) async fn with_fallback<T, F, Fut>(client: OpenGLM, inner: RequestInner, attempt: F) -> Result<Response<T>>
+where
+    F: Fn(OpenGLM, RequestInner, Option<Instant>) -> Fut,
+    Fut: Future<Output = Result<Response<T>>>,
+{
+    // 整个降级过程共用同一个截止时间
+    let deadline = inner.timeouts().or(client.timeouts()).deadline_from(Instant::now());
+    let model = inner.model().cloned();
+    let (Some(policy), Some(model)) = (client.fallback().cloned(), model.clone()) else {
+        let mut response = attempt(client, inner, deadline).await?;
+        response.meta.model = model;
+        return Ok(response);
+    };
+
+    let mut candidates = vec![(client.clone(), model.clone())];
+    for target in policy.candidates(&model) {
+        candidates.push((target.client.clone().unwrap_or_else(|| client.clone()), target.model.clone()));
+    }
+
+    let mut candidates = candidates.into_iter().peekable();
+    let mut fallbacks = Vec::new();
+    loop {
+        let (client, model) = candidates.next().expect("at least the requested model is tried");
+        match attempt(client.clone(), inner.clone().with_model(model.clone()), deadline).await {
+            Ok(mut response) => {
+                response.meta.model = Some(model);
+                response.meta.fallbacks = fallbacks;
+                return Ok(response);
+            },
+            Err(e) if candidates.peek().is_some() && policy.should_fallback(&e) => {
+                attempt_failed(&client, &model, &e);
+                fallbacks.push(FallbackAttempt { model, error: e.to_string() });
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use crate::prelude::*;
+
+    fn completion(model: &str) -> MockResponse {
+        MockResponse::json(&json!({
+            "id": "1", "created": 1711433468, "model": model,
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+        }))
+    }
+
+    fn error(status: StatusCode, code: &str) -> MockResponse {
+        MockResponse::new(status, json!({"error": {"code": code, "message": "error"}}).to_string())
+    }
+
+    fn body(request: &HttpRequest) -> serde_json::Value {
+        serde_json::from_slice(&request.body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let transport = Arc::new(MockTransport::new()
+            .with_response(error(StatusCode::TOO_MANY_REQUESTS, "1302"))
+            .with_response(completion("glm-4-air"))
+            .with_response(error(StatusCode::SERVICE_UNAVAILABLE, "1234"))
+            .with_response(error(StatusCode::SERVICE_UNAVAILABLE, "1234"))
+            .with_response(error(StatusCode::BAD_REQUEST, "1214")));
+        let backup = Arc::new(MockTransport::new().with_response(MockResponse::sse([json!({
+            "id": "2", "created": 1711433468, "model": "glm-4-flash",
+            "choices": [{"index": 0, "finish_reason": "stop", "delta": {"role": "assistant", "content": "你好"}}],
+        })])));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(transport.clone());
+        let client = client.clone().with_fallback(FallbackPolicy::new([Model::Glm4Plus, Model::Glm4Air])
+            .with_target(Model::Glm4Flash, client.with_transport(backup.clone())));
+        let request = |model: Model| client.chat().completions().create()
+            .with_model(model)
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        let response = request(Model::Glm4Plus).send_with_response().await.unwrap();
+        assert_eq!(response.meta.model, Some(Model::Glm4Air));
+        assert_eq!(response.meta.fallbacks.len(), 1);
+        assert_eq!(response.meta.fallbacks[0].model, Model::Glm4Plus);
+
+        let response = request(Model::Glm4Plus).stream().send_with_response().await.unwrap();
+        assert_eq!(response.meta.model, Some(Model::Glm4Flash));
+        assert_eq!(response.meta.fallbacks.len(), 2);
+        assert_eq!(backup.requests().len(), 1);
+
+        // 不在触发条件内的错误直接返回
+        let err = request(Model::Glm4Plus).send().await.unwrap_err();
+        assert!(matches!(err, Error::Api { status: 400, .. }));
+
+        let models: Vec<_> = transport.requests().iter().map(|request| body(request)["model"].clone()).collect();
+        assert_eq!(models, ["glm-4-plus", "glm-4-air", "glm-4-plus", "glm-4-air", "glm-4-plus"]);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_shares_deadline() {
+        let slow_error = || error(StatusCode::TOO_MANY_REQUESTS, "1302").with_chunk_delay(Duration::from_millis(60));
+        let transport = MockTransport::new()
+            .with_response(slow_error())
+            .with_response(slow_error())
+            .with_response(completion("glm-4-flash"));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string())
+            .with_transport(transport)
+            .with_deadline(Duration::from_millis(100))
+            .with_fallback(FallbackPolicy::new([Model::Glm4Plus, Model::Glm4Air, Model::Glm4Flash])
+                .with_triggers([FallbackTrigger::RateLimit]));
+
+        // 第二次尝试时已超出整个请求的截止时间，不会再降级到第三个模型
+        let err = client.chat().completions().create()
+            .with_model(Model::Glm4Plus)
+            .add_message(ChatMessage::User("你好".to_string()))
+            .send().await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { phase: TimeoutPhase::Deadline }));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_not_cached() {
+        let transport = Arc::new(MockTransport::new()
+            .with_response(error(StatusCode::TOO_MANY_REQUESTS, "1302"))
+            .with_response(completion("glm-4-air"))
+            .with_response(completion("glm-4-plus")));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string())
+            .with_transport(transport.clone())
+            .with_cache(ResponseCache::new(MemoryCache::new(16)))
+            .with_fallback(FallbackPolicy::new([Model::Glm4Plus, Model::Glm4Air]));
+        let request = || client.chat().completions().create()
+            .with_model(Model::Glm4Plus)
+            .with_do_sample(false)
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        let response = request().send_with_response().await.unwrap();
+        assert_eq!(response.meta.model, Some(Model::Glm4Air));
+
+        // 降级得到的结果没有缓存，再次请求时由所请求的模型回答
+        let response = request().send_with_response().await.unwrap();
+        assert!(!response.meta.cached);
+        assert_eq!(response.meta.model, Some(Model::Glm4Plus));
+        assert!(request().send_with_response().await.unwrap().meta.cached);
+        assert_eq!(transport.requests().len(), 3);
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::Instant;
+
+use bytes::{Bytes, BytesMut};
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE}, Method, StatusCode};
+
+use crate::cancel::{with_cancel, CancellationToken};
+use crate::chat::completions::request_inner::RequestInner;
+use crate::error::{Error, Result};
+use crate::key_pool::KeyLease;
+use crate::limiter::RateLimitPermit;
+use crate::middleware::Next;
+use crate::openglm::OpenGLM;
+use crate::response::ResponseMeta;
+use crate::send::BoxFuture;
+use crate::timeout::{with_timeout, TimeoutPhase, Timeouts};
+
+pub(crate) const DEFAULT_BASE_URL: &str = "https://open.bigmodel.cn/api/paas/v4";
+
+/// SDK发出的HTTP请求，中间件可以读取和修改
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// 响应体，流式请求按收到的顺序逐块读取
+pub trait ResponseBody: Send {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Bytes>>>;
+}
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Box<dyn ResponseBody>,
+}
+
+impl HttpResponse {
+    /// 使用完整的响应体构造响应
+    pub fn from_bytes(status: StatusCode, headers: HeaderMap, body: impl Into<Bytes>) -> Self {
+        Self {
+            status,
+            headers,
+            body: Box::new(FullBody(Some(body.into()))),
+        }
+    }
+
+    /// 读取全部响应体
+    pub async fn bytes(mut self) -> Result<Bytes> {
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = self.body.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes.freeze())
+    }
+}
+
+impl std::fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpResponse").field("status", &self.status).field("headers", &self.headers).finish()
+    }
+}
+
+struct FullBody(Option<Bytes>);
+
+impl ResponseBody for FullBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Bytes>>> {
+        let chunk = self.0.take();
+        Box::pin(async move { Ok(chunk) })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ApiErrorBody {
+    error: ApiErrorDetail,
+}
+
+#[derive(serde::Deserialize)]
+struct ApiErrorDetail {
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    message: String,
+}
+
+pub(crate) struct Exchange {
+    pub(crate) response: HttpResponse,
+    pub(crate) meta: ResponseMeta,
+    /// 按超时设置计算出的截止时间，读取响应体时使用
+    pub(crate) deadline: Option<Instant>,
+    /// 限流许可，读取完响应体后才能释放
+    pub(crate) permit: Option<RateLimitPermit>,
+    /// 使用密钥池时占用的密钥，读取完响应体后释放
+    pub(crate) lease: Option<KeyLease>,
+}
+
+/// 签名并经由中间件发送chat/completions请求，非2xx状态码转换为Error::Api；deadline由调用方计算，降级重试时共用
+pub(crate) async fn post_completions(client: &OpenGLM, inner: &RequestInner, stream: bool, deadline: Option<Instant>) -> Result<Exchange> {
+    let body = Bytes::from(serde_json::to_vec(&inner.to_body(stream)?)?);
+    post_json(client, "chat/completions", body, inner.headers(), inner.timeouts(), deadline, inner.cancellation()).await
+}
+
+/// 签名并经由中间件向base_url下的path发送JSON请求体
+pub(crate) async fn post_json(
+    client: &OpenGLM,
+    path: &str,
+    body: Bytes,
+    custom_headers: &[(String, String)],
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Exchange> {
+    let timeouts = timeouts.or(client.timeouts());
+
+    // 排队时间计入截止时间
+    let permit = match client.rate_limiter() {
+        Some(rate_limiter) => {
+            let permit = with_timeout(rate_limiter.acquire(), None, deadline, TimeoutPhase::Deadline);
+            Some(with_cancel(permit, cancellation).await.ok_or(Error::Cancelled)??)
+        },
+        None => None,
+    };
+
+    let lease = client.acquire_key()?;
+    let api_key = lease.as_ref().map_or(client.api_key(), KeyLease::api_key);
+    let token = client.auth().token(api_key)?;
+    let mut headers = HeaderMap::new();
+    // 自定义请求头已在validate中校验过
+    for (name, value) in custom_headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value.as_str())) {
+            headers.append(name, value);
+        }
+    }
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Some(token) = token {
+        headers.insert(AUTHORIZATION, HeaderValue::try_from(format!("Bearer {}", &token)).map_err(|_| Error::InvalidApiKey)?);
+    }
+
+    let request = HttpRequest {
+        method: Method::POST,
+        url: format!("{}/{}", client.base_url(), path),
+        headers,
+        body,
+    };
+
+    let sent = Instant::now();
+    let response = Next::new(client.middlewares(), client.transport()).run(request);
+    let response = with_timeout(response, timeouts.headers, deadline, TimeoutPhase::Headers);
+    let response = with_cancel(response, cancellation).await.ok_or(Error::Cancelled)??;
+    let response = response.inspect_err(|e| record_error(lease.as_ref(), e))?;
+
+    let meta = ResponseMeta {
+        status: response.status,
+        headers: response.headers.clone(),
+        elapsed: sent.elapsed(),
+        model: None,
+        fallbacks: Vec::new(),
+        cached: false,
+    };
+
+    if !meta.status.is_success() {
+        let body = with_timeout(response.bytes(), None, deadline, TimeoutPhase::Deadline).await??;
+        let error = api_error(meta.status.as_u16(), &body);
+        record_error(lease.as_ref(), &error);
+        return Err(error);
+    }
+
+    Ok(Exchange { response, meta, deadline, permit, lease })
+}
+
+fn record_error(lease: Option<&KeyLease>, error: &Error) {
+    if let Some(lease) = lease {
+        lease.record_error(error);
+    }
+}
+
+pub(crate) fn api_error(status: u16, body: &Bytes) -> Error {
+    match serde_json::from_slice::<ApiErrorBody>(body) {
+        Ok(ApiErrorBody { error }) => Error::Api { status, code: error.code, message: error.message },
+        Err(_) => Error::Api { status, code: None, message: String::from_utf8_lossy(body).into_owned() },
+    }
+}
+
+pub(crate) fn decode<T: serde::de::DeserializeOwned>(body: &Bytes) -> Result<T> {
+    serde_json::from_slice(body).map_err(|source| Error::Decode {
+        body: String::from_utf8_lossy(body).into_owned(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use reqwest::header::HeaderValue;
+
+    use crate::chat::completions::completions::CompletionsRequestBuilder;
+    use crate::prelude::*;
+
+    fn client(response: MockResponse) -> OpenGLM {
+        OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(MockTransport::new().with_response(response))
+    }
+
+    fn request(client: &OpenGLM) -> CompletionsRequestBuilder {
+        client.chat().completions().create()
+            .with_model(Model::Glm4)
+            .add_message(ChatMessage::User("你好".to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_api_error_without_error_object() {
+        let client = client(MockResponse::new(reqwest::StatusCode::BAD_GATEWAY, "<html>Bad Gateway</html>"));
+        let err = request(&client).send().await.unwrap_err();
+        assert!(matches!(err, Error::Api { status: 502, code: None, ref message } if message == "<html>Bad Gateway</html>"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_body() {
+        let client = client(MockResponse::new(reqwest::StatusCode::OK, r#"{"id": "1", "choices": "#));
+        let err = request(&client).send().await.unwrap_err();
+        assert!(matches!(err, Error::Decode { ref body, .. } if body == r#"{"id": "1", "choices": "#));
+    }
+
+    #[tokio::test]
+    async fn test_response_meta() {
+        let transport = Arc::new(MockTransport::new().with_response(MockResponse::json(&serde_json::json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+        })).with_header(reqwest::header::HeaderName::from_static("x-request-id"), HeaderValue::from_static("req-1"))));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(transport.clone());
+
+        let response = request(&client).send_with_response().await.unwrap();
+        assert_eq!(response.meta.status, reqwest::StatusCode::OK);
+        assert_eq!(response.meta.header("x-request-id"), Some("req-1"));
+        assert_eq!(response.meta.model, Some(Model::Glm4));
+        assert!(response.meta.fallbacks.is_empty());
+        assert!(!response.meta.cached);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&response.body).unwrap()["id"], "1");
+        assert_eq!(transport.requests()[0].url, "https://open.bigmodel.cn/api/paas/v4/chat/completions");
+    }
+
+    // 本地端口只接受TCP连接而不完成TLS握手，建立连接阶段超时
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string())
+            .with_base_url(format!("https://{}", listener.local_addr().unwrap()))
+            .with_connect_timeout(std::time::Duration::from_millis(100));
+
+        let err = request(&client).send().await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { phase: TimeoutPhase::Connect }), "{:?}", err);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+
+use crate::authen::parse_api_key;
+use crate::chat::completions::result::Usage;
+use crate::error::{Error, Result};
+
+const DEFAULT_EVICTION: Duration = Duration::from_secs(60);
+
+/// 选择密钥的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStrategy {
+    RoundRobin,
+    /// 选择进行中请求最少的密钥
+    LeastInFlight,
+    /// 按权重平滑轮询
+    Weighted,
+}
+
+/// 单个密钥的使用统计，id为api_key中点号之前的部分
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyStats {
+    pub id: String,
+    pub weight: u32,
+    pub in_flight: usize,
+    pub requests: u64,
+    pub failures: u64,
+    pub evictions: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// 当前是否因鉴权或额度错误被暂时移出
+    pub evicted: bool,
+}
+
+struct KeyEntry {
+    api_key: String,
+    in_flight: AtomicUsize,
+    evicted_until: Mutex<Option<Instant>>,
+    stats: Mutex<KeyStats>,
+}
+
+impl KeyEntry {
+    fn available(&self, now: Instant) -> bool {
+        self.evicted_until.lock().unwrap().is_none_or(|until| until <= now)
+    }
+}
+
+/// 多个api_key组成的密钥池，客户端每次请求从中选择一个。
+///
+/// 返回鉴权或额度错误的密钥会被暂时移出，只影响之后的请求；全部被移出时选择最早恢复的密钥。
+pub struct KeyPool {
+    strategy: KeyStrategy,
+    eviction: Duration,
+    keys: Vec<KeyEntry>,
+    cursor: AtomicUsize,
+    // 平滑加权轮询中各密钥的当前权重
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl KeyPool {
+    pub fn new(strategy: KeyStrategy) -> Self {
+        Self {
+            strategy,
+            eviction: DEFAULT_EVICTION,
+            keys: Vec::new(),
+            cursor: AtomicUsize::new(0),
+            current_weights: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_key(self, api_key: String) -> Result<Self> {
+        self.with_weighted_key(api_key, 1)
+    }
+
+    /// 权重只在KeyStrategy::Weighted时生效
+    pub fn with_weighted_key(mut self, api_key: String, weight: u32) -> Result<Self> {
+        let (id, _) = parse_api_key(&api_key)?;
+        let stats = KeyStats { id: id.to_string(), weight, ..Default::default() };
+        self.keys.push(KeyEntry {
+            api_key,
+            in_flight: AtomicUsize::new(0),
+            evicted_until: Mutex::new(None),
+            stats: Mutex::new(stats),
+        });
+        self.current_weights.get_mut().unwrap().push(0);
+        Ok(self)
+    }
+
+    /// 密钥返回鉴权或额度错误后被移出的时长，默认60秒
+    pub fn with_eviction(self, eviction: Duration) -> Self {
+        Self {
+            eviction,
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn api_keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(|key| key.api_key.as_str())
+    }
+
+    pub fn stats(&self) -> Vec<KeyStats> {
+        let now = Instant::now();
+        self.keys.iter().map(|key| KeyStats {
+            in_flight: key.in_flight.load(Ordering::Relaxed),
+            evicted: !key.available(now),
+            ..key.stats.lock().unwrap().clone()
+        }).collect()
+    }
+
+    fn select(&self) -> Result<usize> {
+        if self.keys.is_empty() {
+            return Err(Error::InvalidApiKey);
+        }
+
+        let now = Instant::now();
+        let available: Vec<usize> = (0..self.keys.len()).filter(|&index| self.keys[index].available(now)).collect();
+        if available.is_empty() {
+            let soonest = (0..self.keys.len()).min_by_key(|&index| *self.keys[index].evicted_until.lock().unwrap());
+            return Ok(soonest.unwrap_or_default());
+        }
+
+        let index = match self.strategy {
+            KeyStrategy::RoundRobin => available[self.cursor.fetch_add(1, Ordering::Relaxed) % available.len()],
+            KeyStrategy::LeastInFlight => {
+                // 进行中请求数相同时轮流选择
+                let offset = self.cursor.fetch_add(1, Ordering::Relaxed);
+                let rotated = available.iter().cycle().skip(offset % available.len()).take(available.len());
+                *rotated.min_by_key(|&&index| self.keys[index].in_flight.load(Ordering::Relaxed)).expect("available is not empty")
+            },
+            KeyStrategy::Weighted => {
+                let mut current_weights = self.current_weights.lock().unwrap();
+                let weight = |index: usize| self.keys[index].stats.lock().unwrap().weight as i64;
+                let total: i64 = available.iter().map(|&index| weight(index)).sum();
+                for &index in &available {
+                    current_weights[index] += weight(index);
+                }
+                let index = *available.iter().max_by_key(|&&index| (current_weights[index], std::cmp::Reverse(index))).expect("available is not empty");
+                current_weights[index] -= total;
+                index
+            },
+        };
+        Ok(index)
+    }
+
+    /// 选择一个密钥，返回的KeyLease在请求结束前保持占用
+    pub(crate) fn acquire(self: &Arc<Self>) -> Result<KeyLease> {
+        let index = self.select()?;
+        let key = &self.keys[index];
+        key.in_flight.fetch_add(1, Ordering::Relaxed);
+        key.stats.lock().unwrap().requests += 1;
+
+        Ok(KeyLease { pool: self.clone(), index })
+    }
+}
+
+/// 一次请求占用的密钥，释放时减少进行中请求数
+pub(crate) struct KeyLease {
+    pool: Arc<KeyPool>,
+    index: usize,
+}
+
+impl KeyLease {
+    fn key(&self) -> &KeyEntry {
+        &self.pool.keys[self.index]
+    }
+
+    pub(crate) fn api_key(&self) -> &str {
+        &self.key().api_key
+    }
+
+    pub(crate) fn id(&self) -> String {
+        self.key().stats.lock().unwrap().id.clone()
+    }
+
+    pub(crate) fn record_usage(&self, usage: &Usage) {
+        let mut stats = self.key().stats.lock().unwrap();
+        stats.prompt_tokens += usage.prompt_tokens.max(0) as u64;
+        stats.completion_tokens += usage.completion_tokens.max(0) as u64;
+    }
+
+    /// 记录接口或传输层的失败，取消、超时等客户端错误不计入。
+    ///
+    /// 鉴权错误和账户类错误（错误码11xx，如欠费）会使密钥被暂时移出，本次请求仍返回该错误，不会换用其他密钥重试。
+    pub(crate) fn record_error(&self, error: &Error) {
+        if !matches!(error, Error::Api { .. } | Error::Transport(_) | Error::Reqwest(_)) {
+            return;
+        }
+        let key = self.key();
+        key.stats.lock().unwrap().failures += 1;
+
+        let Error::Api { status, code, .. } = error else {
+            return;
+        };
+        if *status == 401 || *status == 403 || code.as_deref().is_some_and(|code| code.starts_with("11")) {
+            *key.evicted_until.lock().unwrap() = Some(Instant::now() + self.pool.eviction);
+            key.stats.lock().unwrap().evictions += 1;
+        }
+    }
+}
+
+impl Drop for KeyLease {
+    fn drop(&mut self) {
+        self.key().in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(strategy: KeyStrategy) -> Arc<KeyPool> {
+        Arc::new(KeyPool::new(strategy)
+            .with_weighted_key("a.secret".to_string(), 3).unwrap()
+            .with_weighted_key("b.secret".to_string(), 1).unwrap())
+    }
+
+    fn pick(pool: &Arc<KeyPool>, count: usize) -> String {
+        (0..count).map(|_| pool.acquire().unwrap().api_key()[..1].to_string()).collect()
+    }
+
+    #[test]
+    fn test_strategies() {
+        assert_eq!(pick(&pool(KeyStrategy::RoundRobin), 4), "abab");
+        assert_eq!(pick(&pool(KeyStrategy::Weighted), 8), "aabaaaba");
+
+        let pool = pool(KeyStrategy::LeastInFlight);
+        let held = pool.acquire().unwrap();
+        assert_eq!(pick(&pool, 2), "bb");
+        drop(held);
+        assert_eq!(pool.stats().iter().map(|stats| stats.in_flight).sum::<usize>(), 0);
+
+        assert!(KeyPool::new(KeyStrategy::RoundRobin).with_key("invalid".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let pool = pool(KeyStrategy::RoundRobin);
+        let lease = pool.acquire().unwrap();
+        lease.record_error(&Error::Api { status: 429, code: Some("1113".to_string()), message: "欠费".to_string() });
+        lease.record_usage(&Usage { prompt_tokens: 10, completion_tokens: 5, total_tokens: 15 });
+        drop(lease);
+        assert_eq!(pick(&pool, 3), "bbb");
+
+        let stats = pool.stats();
+        assert!(stats[0].evicted);
+        assert_eq!((stats[0].id.as_str(), stats[0].failures, stats[0].prompt_tokens), ("a", 1, 10));
+
+        // 限流不会移出密钥，取消和超时不计入失败
+        let lease = pool.acquire().unwrap();
+        lease.record_error(&Error::Api { status: 429, code: Some("1302".to_string()), message: "限流".to_string() });
+        lease.record_error(&Error::Cancelled);
+        lease.record_error(&Error::Timeout { phase: crate::timeout::TimeoutPhase::Deadline });
+        assert!(!pool.stats()[1].evicted);
+        assert_eq!(pool.stats()[1].failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_key_pool() {
+        use crate::authen::verify;
+        use crate::prelude::*;
+
+        let transport = Arc::new(MockTransport::new()
+            .with_response(MockResponse::new(reqwest::StatusCode::UNAUTHORIZED, r#"{"error":{"code":"1000","message":"身份验证失败"}}"#))
+            .with_response(MockResponse::json(&serde_json::json!({
+                "id": "1", "created": 1711433468, "model": "glm-4",
+                "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12},
+            }))));
+        let pool = KeyPool::new(KeyStrategy::RoundRobin)
+            .with_key("a.secret".to_string()).unwrap()
+            .with_key("b.secret".to_string()).unwrap();
+        let client = OpenGLM::new(String::new()).with_key_pool(pool).with_transport(transport.clone());
+        let request = || client.chat().completions().create()
+            .with_model(Model::Glm4)
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        assert!(matches!(request().send().await, Err(Error::Api { status: 401, .. })));
+        request().send().await.unwrap();
+
+        let tokens: Vec<String> = transport.requests().iter()
+            .map(|request| request.headers["authorization"].to_str().unwrap().trim_start_matches("Bearer ").to_string())
+            .collect();
+        assert!(verify(&tokens[0], "a.secret").is_ok());
+        assert!(verify(&tokens[1], "b.secret").is_ok());
+
+        let stats = client.key_pool().unwrap().stats();
+        assert!(stats[0].evicted);
+        assert_eq!((stats[1].requests, stats[1].prompt_tokens, stats[1].completion_tokens, stats[1].in_flight), (1, 10, 2, 0));
+    }
+}
@@ -1,34 +1,100 @@
 pub mod openglm;
+pub mod model;
 pub mod chat;
 pub mod send;
 pub mod error;
 pub mod authen;
+pub mod response;
+pub mod timeout;
+pub mod cancel;
+pub mod limiter;
+pub mod fallback;
+pub mod key_pool;
+pub mod cache;
+pub mod usage;
+mod telemetry;
+pub mod http;
+pub mod middleware;
+pub mod transport;
+pub mod cassette;
+pub mod provider;
+#[cfg(any(feature = "mock-server", feature = "proxy"))]
+mod server;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+#[cfg(feature = "proxy")]
+pub mod proxy;
 
 pub mod prelude {
     pub use super::openglm::OpenGLM;
+    pub use super::model::Model;
     pub use super::error::{Error, Result};
-    pub use super::send::Sendable;
-    pub use super::chat::{chat::*, tools::*, message::*, completions::{result::*, request_inner::{Unpack, RequestBuild}}};
+    pub use super::send::{Sendable, BoxFuture};
+    pub use super::response::{Response, ResponseMeta};
+    pub use super::timeout::{Timeouts, TimeoutPhase};
+    pub use super::cancel::CancellationToken;
+    pub use super::http::{HttpRequest, HttpResponse, ResponseBody};
+    pub use super::middleware::{Middleware, Next, Logging, InjectHeaders};
+    pub use super::transport::{HttpTransport, ReqwestTransport, MockTransport, MockResponse};
+    pub use super::cassette::{Cassette, RecordingTransport, ReplayTransport};
+    pub use super::limiter::{RateLimiter, RateLimitPermit, LimiterStats};
+    pub use super::usage::{UsageTracker, UsageReport, UsageTotals, PriceTable, Price, Budget, BudgetWarning};
+    pub use super::cache::{CacheBackend, MemoryCache, DiskCache, ResponseCache};
+    pub use super::key_pool::{KeyPool, KeyStrategy, KeyStats};
+    pub use super::fallback::{FallbackPolicy, FallbackTrigger, FallbackAttempt};
+    pub use super::provider::{ChatProvider, OpenAICompatible, ProviderConfig};
+    pub use super::chat::{chat::*, tools::*, message::*, conversation::*, context::*, completions::{result::*, validation::*, typed::*, stream_completions::{CompletionDeltaIter, StreamStatus}, request_inner::{Unpack, RequestBuild}}};
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use super::prelude::*;
 
     #[tokio::test]
     async fn test_chat() {
-        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string());
+        let transport = Arc::new(MockTransport::new().with_response(MockResponse::json(&serde_json::json!({
+            "id": "8650001",
+            "request_id": "8650001",
+            "created": 1711433468,
+            "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "从前，有一只小兔子……"}}],
+            "usage": {"prompt_tokens": 80, "completion_tokens": 12, "total_tokens": 92},
+        }))));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(transport.clone());
         let result = client.chat().completions().create()
             .with_model("glm-4".to_string())
             .add_message(ChatMessage::System("你是一个聪明且富有创造力的小说作家".to_string()))
             .add_message(ChatMessage::User("请你作为童话故事大王，写一篇短篇童话故事，故事的主题是要永远保持一颗善良的心，要能够激发儿童的学习兴趣和想象力，同时也能够帮助儿童更好地理解和接受故事中所蕴含的道理和价值观。".to_string()))
             .send().await.unwrap();
         println!("{:?}", result);
+
+        assert_eq!(result.choices[0].finish_reason, FinishReason::Stop);
+        assert!(matches!(result.choices[0].message, ChatMessage::Assistant(_)));
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].headers["authorization"].to_str().unwrap().starts_with("Bearer "));
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["model"], "glm-4");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 2);
     }
 
     #[tokio::test]
     async fn test_stream_chat() {
-        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string());
+        let chunk = |content: &str, finish_reason: Option<&str>| serde_json::json!({
+            "id": "8650002",
+            "created": 1711433468,
+            "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": finish_reason, "delta": {"role": "assistant", "content": content}}],
+        });
+        let transport = MockTransport::new().with_response(MockResponse::sse([
+            chunk("从前，", None),
+            chunk("有一只小兔子……", None),
+            chunk("", Some("stop")),
+        ]));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(transport);
         let mut result = client.chat().completions().create()
             .with_model("glm-4".to_string())
             .add_message(ChatMessage::System("你是一个聪明且富有创造力的小说作家".to_string()))
@@ -36,8 +102,14 @@ mod tests {
             .stream()
             .send().await.unwrap();
 
+        let mut count = 0;
         while let Some(delta) = result.next().await.unwrap() {
             println!("{:?}", delta);
+            count += 1;
         }
+
+        assert_eq!(count, 3);
+        assert_eq!(result.status(), StreamStatus::Finished);
+        assert!(matches!(result.partial_message().unwrap(), ChatMessage::Assistant(content) if content == "从前，有一只小兔子……"));
     }
 }
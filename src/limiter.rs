@@ -0,0 +1,237 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::{Error, Result};
+
+const TOKEN_WINDOW: Duration = Duration::from_secs(60);
+
+struct BucketState {
+    tokens: f64,
+    last: Instant,
+}
+
+// 每秒请求数的令牌桶，等待时持有锁，保证按到达顺序放行
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: tokio::sync::Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            state: tokio::sync::Mutex::new(BucketState { tokens: capacity, last: Instant::now() }),
+        }
+    }
+
+    async fn acquire(&self) {
+        let mut state = self.state.lock().await;
+        loop {
+            let now = Instant::now();
+            state.tokens = (state.tokens + now.duration_since(state.last).as_secs_f64() * self.rate).min(self.capacity);
+            state.last = now;
+
+            if state.tokens >= 1.0 {
+                state.tokens -= 1.0;
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_secs_f64((1.0 - state.tokens) / self.rate)).await;
+        }
+    }
+}
+
+// 最近一分钟内消耗的token数
+struct TokenBudget {
+    limit: u64,
+    queue: tokio::sync::Mutex<()>,
+    window: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl TokenBudget {
+    fn used(&self, now: Instant) -> (u64, Option<Instant>) {
+        let mut window = self.window.lock().unwrap();
+        while window.front().is_some_and(|(at, _)| now.duration_since(*at) >= TOKEN_WINDOW) {
+            window.pop_front();
+        }
+        (window.iter().map(|(_, tokens)| tokens).sum(), window.front().map(|(at, _)| *at + TOKEN_WINDOW))
+    }
+
+    async fn acquire(&self) {
+        let _turn = self.queue.lock().await;
+        loop {
+            let now = Instant::now();
+            let (used, next_expiry) = self.used(now);
+            let Some(next_expiry) = next_expiry.filter(|_| used >= self.limit) else {
+                return;
+            };
+            tokio::time::sleep(next_expiry.saturating_duration_since(now)).await;
+        }
+    }
+
+    fn record(&self, tokens: u64) {
+        self.window.lock().unwrap().push_back((Instant::now(), tokens));
+    }
+}
+
+/// 排队等待的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimiterStats {
+    pub requests: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl LimiterStats {
+    pub fn average_wait(&self) -> Duration {
+        match self.requests {
+            0 => Duration::ZERO,
+            requests => self.total_wait / requests as u32,
+        }
+    }
+}
+
+/// 客户端限流：每秒请求数、同时进行中的请求数以及每分钟token数，均按到达顺序排队
+pub struct RateLimiter {
+    bucket: Option<TokenBucket>,
+    concurrency: Option<Arc<Semaphore>>,
+    budget: Option<TokenBudget>,
+    stats: Mutex<LimiterStats>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            bucket: None,
+            concurrency: None,
+            budget: None,
+            stats: Mutex::new(LimiterStats::default()),
+        }
+    }
+
+    /// rate必须为有限的正数，否则返回Error::InvalidConfig
+    pub fn with_requests_per_second(self, rate: f64) -> Result<Self> {
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(Error::InvalidConfig(format!("requests per second must be a positive number, got {}", rate)));
+        }
+        Ok(Self {
+            bucket: Some(TokenBucket::new(rate)),
+            ..self
+        })
+    }
+
+    /// max为0时所有请求都会一直等待，因此返回Error::InvalidConfig
+    pub fn with_max_concurrency(self, max: usize) -> Result<Self> {
+        if max == 0 {
+            return Err(Error::InvalidConfig("max concurrency must be at least 1".to_string()));
+        }
+        Ok(Self {
+            concurrency: Some(Arc::new(Semaphore::new(max))),
+            ..self
+        })
+    }
+
+    /// 根据响应中的Usage统计，最近一分钟消耗的token数达到上限后暂停发送。
+    /// limit为0时所有请求都会一直等待，因此返回Error::InvalidConfig
+    pub fn with_tokens_per_minute(self, limit: u64) -> Result<Self> {
+        if limit == 0 {
+            return Err(Error::InvalidConfig("tokens per minute must be at least 1".to_string()));
+        }
+        Ok(Self {
+            budget: Some(TokenBudget {
+                limit,
+                queue: tokio::sync::Mutex::new(()),
+                window: Mutex::new(VecDeque::new()),
+            }),
+            ..self
+        })
+    }
+
+    /// 等待直到可以发送请求，返回的permit在请求结束前需要一直持有
+    pub async fn acquire(&self) -> RateLimitPermit {
+        let started = Instant::now();
+
+        let concurrency = match &self.concurrency {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.expect("semaphore is never closed")),
+            None => None,
+        };
+        if let Some(budget) = &self.budget {
+            budget.acquire().await;
+        }
+        if let Some(bucket) = &self.bucket {
+            bucket.acquire().await;
+        }
+
+        let waited = started.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.total_wait += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+
+        RateLimitPermit { _concurrency: concurrency, waited }
+    }
+
+    pub fn record_usage(&self, total_tokens: u64) {
+        if let Some(budget) = &self.budget {
+            budget.record(total_tokens);
+        }
+    }
+
+    pub fn stats(&self) -> LimiterStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+pub struct RateLimitPermit {
+    _concurrency: Option<OwnedSemaphorePermit>,
+    waited: Duration,
+}
+
+impl RateLimitPermit {
+    /// 排队等待的时间
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrency_and_token_budget() {
+        let limiter = RateLimiter::new()
+            .with_max_concurrency(1).unwrap()
+            .with_tokens_per_minute(100).unwrap();
+
+        let permit = limiter.acquire().await;
+        let blocked = tokio::time::timeout(Duration::from_millis(20), limiter.acquire()).await;
+        assert!(blocked.is_err());
+        drop(permit);
+
+        limiter.record_usage(100);
+        let blocked = tokio::time::timeout(Duration::from_millis(20), limiter.acquire()).await;
+        assert!(blocked.is_err());
+        assert_eq!(limiter.stats().requests, 1);
+    }
+
+    #[test]
+    fn test_rejects_invalid_limits() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(RateLimiter::new().with_requests_per_second(rate), Err(Error::InvalidConfig(_))));
+        }
+        assert!(matches!(RateLimiter::new().with_max_concurrency(0), Err(Error::InvalidConfig(_))));
+        assert!(matches!(RateLimiter::new().with_tokens_per_minute(0), Err(Error::InvalidConfig(_))));
+        assert!(RateLimiter::new().with_requests_per_second(0.5).is_ok());
+    }
+}
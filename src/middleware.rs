@@ -0,0 +1,187 @@
+use std::{sync::Arc, time::Instant};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+
+use crate::chat::completions::validation::{check_header, ValidationError, ValidationIssue};
+use crate::error::{Error, Result};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::transport::HttpTransport;
+use crate::send::BoxFuture;
+
+/// 包裹SDK发出的每一个HTTP请求，可以修改请求、替换响应或记录日志。
+///
+/// 调用next.run(request)将请求交给下一个中间件，最内层为HttpTransport。
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>>;
+}
+
+/// 中间件链中剩余的部分
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    transport: &'a dyn HttpTransport,
+}
+
+impl <'a> Next<'a> {
+    pub(crate) fn new(middlewares: &'a [Arc<dyn Middleware>], transport: &'a dyn HttpTransport) -> Self {
+        Self {
+            middlewares,
+            transport,
+        }
+    }
+
+    pub fn run(self, request: HttpRequest) -> BoxFuture<'a, Result<HttpResponse>> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(request, Next { middlewares: rest, transport: self.transport }),
+            None => self.transport.send(request),
+        }
+    }
+}
+
+/// 记录请求和响应的日志，Authorization请求头始终被隐去
+pub struct Logging {
+    sink: Box<dyn Fn(String) + Send + Sync>,
+    log_body: bool,
+}
+
+impl Logging {
+    pub fn new(sink: impl Fn(String) + Send + Sync + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            log_body: false,
+        }
+    }
+
+    /// 输出到标准错误
+    pub fn stderr() -> Self {
+        Self::new(|line| eprintln!("{}", line))
+    }
+
+    /// 同时记录请求体，其中包含完整的对话内容，默认关闭
+    pub fn with_body(self, log_body: bool) -> Self {
+        Self {
+            log_body,
+            ..self
+        }
+    }
+}
+
+pub(crate) fn redacted_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    if headers.contains_key(AUTHORIZATION) {
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("[REDACTED]"));
+    }
+    headers
+}
+
+impl Middleware for Logging {
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let mut line = format!("--> {} {} {:?}", request.method, request.url, redacted_headers(&request.headers));
+            if self.log_body {
+                line.push(' ');
+                line.push_str(&String::from_utf8_lossy(&request.body));
+            }
+            (self.sink)(line);
+
+            let started = Instant::now();
+            let result = next.run(request).await;
+            match &result {
+                Ok(response) => (self.sink)(format!("<-- {} {:?}", response.status, started.elapsed())),
+                Err(e) => (self.sink)(format!("<-- error {} {:?}", e, started.elapsed())),
+            }
+            result
+        })
+    }
+}
+
+/// 为每个请求添加固定的请求头，如链路追踪id
+#[derive(Default)]
+pub struct InjectHeaders {
+    headers: HeaderMap,
+}
+
+impl InjectHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 同名请求头可多次添加，发送时替换请求中该名称的全部值。Authorization和Content-Type由SDK设置，不允许添加
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Result<Self> {
+        let checked = value.to_str().map_err(|e| e.to_string()).and_then(|value| check_header(name.as_str(), value));
+        if let Err(reason) = checked {
+            return Err(Error::Validation(ValidationError { issues: vec![ValidationIssue::InvalidHeader { name: name.to_string(), reason }] }));
+        }
+
+        self.headers.append(name, value);
+        Ok(self)
+    }
+}
+
+impl Middleware for InjectHeaders {
+    fn handle<'a>(&'a self, mut request: HttpRequest, next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+        request.headers.extend(self.headers.clone());
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use reqwest::{header::CONTENT_TYPE, Method, StatusCode};
+
+    use super::*;
+
+    // 记录收到的请求并直接返回，不发出网络请求
+    struct Capture(Mutex<Option<HttpRequest>>);
+
+    impl Middleware for Capture {
+        fn handle<'a>(&'a self, request: HttpRequest, _next: Next<'a>) -> BoxFuture<'a, Result<HttpResponse>> {
+            *self.0.lock().unwrap() = Some(request);
+            Box::pin(async { Ok(HttpResponse::from_bytes(StatusCode::TOO_MANY_REQUESTS, HeaderMap::new(), "{}")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_order_and_redaction() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let capture = Arc::new(Capture(Mutex::new(None)));
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Logging::new(move |line| sink.lock().unwrap().push(line))),
+            Arc::new(InjectHeaders::new()
+                .with_header(HeaderName::from_static("x-trace-id"), HeaderValue::from_static("t1")).unwrap()
+                .with_header(HeaderName::from_static("x-tag"), HeaderValue::from_static("a")).unwrap()
+                .with_header(HeaderName::from_static("x-tag"), HeaderValue::from_static("b")).unwrap()),
+            capture.clone(),
+        ];
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret.jwt"));
+        let request = HttpRequest { method: Method::POST, url: "http://localhost/".to_string(), headers, body: Default::default() };
+
+        let transport = crate::transport::MockTransport::new();
+        let response = Next::new(&middlewares, &transport).run(request).await.unwrap();
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+
+        let captured = capture.0.lock().unwrap().take().unwrap();
+        assert_eq!(captured.headers["x-trace-id"], "t1");
+        assert_eq!(captured.headers.get_all("x-tag").iter().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(captured.headers[AUTHORIZATION], "Bearer secret.jwt");
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(!lines[0].contains("secret.jwt"));
+        assert!(lines[1].starts_with("<-- 429"));
+    }
+
+    #[test]
+    fn test_inject_headers_rejects_sdk_headers() {
+        for name in [AUTHORIZATION, CONTENT_TYPE] {
+            let Err(Error::Validation(e)) = InjectHeaders::new().with_header(name, HeaderValue::from_static("x")) else {
+                panic!("header should be rejected");
+            };
+            assert!(matches!(e.issues[0], ValidationIssue::InvalidHeader { .. }));
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::{collections::VecDeque, io, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
+
+use serde_json::json;
+use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}, task::JoinHandle};
+
+use crate::authen::verify;
+use crate::chat::context::estimate_tokens;
+use crate::server::{read_request, write_event_stream_head, write_response};
+
+const CHAT_COMPLETIONS_PATH: &str = "/api/paas/v4/chat/completions";
+// 流式回复中每个片段包含的字符数
+const STREAM_CHUNK_CHARS: usize = 4;
+
+/// mock服务对一个请求的回复，未设置时按echo或固定文本回复
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MockReply {
+    Text { content: String },
+    ToolCall { name: String, arguments: serde_json::Value },
+    /// 返回指定状态码和平台格式的错误，如401、429、500
+    Error { status: u16, code: String, message: String },
+    /// 流式请求发送一半后断开，不发送[DONE]；非流式请求返回被截断的JSON
+    TruncatedStream { content: String },
+}
+
+struct ServerState {
+    api_keys: Vec<String>,
+    latency: Duration,
+    chunk_latency: Duration,
+    echo: bool,
+    script: Mutex<VecDeque<MockReply>>,
+    requests: Mutex<Vec<serde_json::Value>>,
+    next_id: AtomicU64,
+}
+
+/// 本地的GLM接口模拟服务，提供/api/paas/v4/chat/completions的JSON和SSE两种返回。
+///
+/// 设置了api_key时校验Authorization中的token，否则只要求携带Bearer token。
+pub struct MockServer {
+    state: ServerState,
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self {
+            state: ServerState {
+                api_keys: Vec::new(),
+                latency: Duration::ZERO,
+                chunk_latency: Duration::ZERO,
+                echo: false,
+                script: Mutex::new(VecDeque::new()),
+                requests: Mutex::new(Vec::new()),
+                next_id: AtomicU64::new(1),
+            },
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.state.api_keys.push(api_key);
+        self
+    }
+
+    /// 返回响应头前的延迟
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.state.latency = latency;
+        self
+    }
+
+    /// 流式回复每个片段之间的延迟
+    pub fn with_chunk_latency(mut self, latency: Duration) -> Self {
+        self.state.chunk_latency = latency;
+        self
+    }
+
+    /// 没有预设回复时，原样返回最后一条用户消息
+    pub fn with_echo(mut self, echo: bool) -> Self {
+        self.state.echo = echo;
+        self
+    }
+
+    /// 追加预设回复，按请求到达的顺序依次使用
+    pub fn with_reply(self, reply: MockReply) -> Self {
+        self.state.script.lock().unwrap().push_back(reply);
+        self
+    }
+
+    pub async fn bind(self, addr: &str) -> io::Result<MockServerHandle> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(self.state);
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        // 连接中途断开等错误只影响当前请求
+                        let _ = handle_connection(stream, &state).await;
+                    });
+                }
+            }
+        });
+
+        Ok(MockServerHandle { addr, state, task })
+    }
+}
+
+pub struct MockServerHandle {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+    task: JoinHandle<()>,
+}
+
+impl MockServerHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 传给OpenGLM::with_base_url的地址
+    pub fn base_url(&self) -> String {
+        format!("http://{}/api/paas/v4", self.addr)
+    }
+
+    /// 已收到的请求体
+    pub fn requests(&self) -> Vec<serde_json::Value> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// 运行时追加预设回复
+    pub fn push_reply(&self, reply: MockReply) {
+        self.state.script.lock().unwrap().push_back(reply);
+    }
+
+    pub fn shutdown(self) {
+        self.task.abort();
+    }
+
+    /// 一直运行直到服务停止
+    pub async fn wait(self) {
+        let _ = self.task.await;
+    }
+}
+
+async fn write_error(stream: &mut TcpStream, status: u16, code: &str, message: &str) -> io::Result<()> {
+    let body = json!({"error": {"code": code, "message": message}}).to_string();
+    write_response(stream, status, "application/json", body.as_bytes()).await
+}
+
+fn authorized(state: &ServerState, authorization: Option<&str>) -> bool {
+    let Some(token) = authorization.and_then(|authorization| authorization.strip_prefix("Bearer ")) else {
+        return false;
+    };
+
+    state.api_keys.is_empty() || state.api_keys.iter().any(|api_key| verify(token, api_key).is_ok())
+}
+
+fn last_user_content(body: &serde_json::Value) -> String {
+    let messages = body["messages"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let Some(message) = messages.iter().rev().find(|message| message["role"] == "user") else {
+        return String::new();
+    };
+
+    match &message["content"] {
+        serde_json::Value::String(content) => content.clone(),
+        content => content.to_string(),
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, state: &ServerState) -> io::Result<()> {
+    let Some(request) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    if request.method != "POST" || request.path != CHAT_COMPLETIONS_PATH {
+        return write_error(&mut stream, 404, "1002", "not found").await;
+    }
+    if !authorized(state, request.authorization.as_deref()) {
+        return write_error(&mut stream, 401, "1000", "身份验证失败").await;
+    }
+    if request.chunked {
+        return write_error(&mut stream, 411, "1210", "chunked request body is not supported").await;
+    }
+    let Ok(body) = serde_json::from_slice::<serde_json::Value>(&request.body) else {
+        return write_error(&mut stream, 400, "1210", "invalid request body").await;
+    };
+    state.requests.lock().unwrap().push(body.clone());
+
+    if !state.latency.is_zero() {
+        tokio::time::sleep(state.latency).await;
+    }
+
+    let reply = state.script.lock().unwrap().pop_front().unwrap_or_else(|| MockReply::Text {
+        content: if state.echo { last_user_content(&body) } else { "这是来自openglm-mock的回复".to_string() },
+    });
+    let prompt_tokens = estimate_tokens(&body["messages"].to_string());
+    let model = body["model"].as_str().unwrap_or("glm-4").to_string();
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+    let reply = Reply { id, model, prompt_tokens, reply };
+
+    if let MockReply::Error { status, code, message } = &reply.reply {
+        return write_error(&mut stream, *status, code, message).await;
+    }
+
+    if body["stream"] == true {
+        write_stream(&mut stream, state, &reply).await
+    } else {
+        let body = reply.completion().to_string();
+        let body = match reply.reply {
+            MockReply::TruncatedStream { .. } => &body.as_bytes()[..body.len() / 2],
+            _ => body.as_bytes(),
+        };
+        write_response(&mut stream, 200, "application/json", body).await
+    }
+}
+
+struct Reply {
+    id: String,
+    model: String,
+    prompt_tokens: usize,
+    reply: MockReply,
+}
+
+impl Reply {
+    fn created() -> u64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default()
+    }
+
+    fn usage(&self, completion: &str) -> serde_json::Value {
+        let completion_tokens = estimate_tokens(completion);
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": self.prompt_tokens + completion_tokens,
+        })
+    }
+
+    fn tool_call(&self, name: &str, arguments: &serde_json::Value) -> serde_json::Value {
+        json!({
+            "id": format!("call_{}", self.id),
+            "type": "function",
+            "function": {"name": name, "arguments": arguments.to_string()},
+        })
+    }
+
+    fn completion(&self) -> serde_json::Value {
+        let (message, finish_reason, completion) = match &self.reply {
+            MockReply::ToolCall { name, arguments } => (
+                json!({"role": "assistant", "tool_calls": [self.tool_call(name, arguments)]}),
+                "tool_calls",
+                arguments.to_string(),
+            ),
+            MockReply::Text { content } | MockReply::TruncatedStream { content } => (
+                json!({"role": "assistant", "content": content}),
+                "stop",
+                content.clone(),
+            ),
+            MockReply::Error { .. } => unreachable!("errors are written before the body"),
+        };
+
+        json!({
+            "id": self.id,
+            "request_id": self.id,
+            "created": Self::created(),
+            "model": self.model,
+            "choices": [{"index": 0, "finish_reason": finish_reason, "message": message}],
+            "usage": self.usage(&completion),
+        })
+    }
+
+    fn chunk(&self, delta: serde_json::Value, finish_reason: Option<&str>, usage: Option<serde_json::Value>) -> String {
+        let mut chunk = json!({
+            "id": self.id,
+            "created": Self::created(),
+            "model": self.model,
+            "choices": [{"index": 0, "delta": delta}],
+        });
+        if let Some(finish_reason) = finish_reason {
+            chunk["choices"][0]["finish_reason"] = json!(finish_reason);
+        }
+        if let Some(usage) = usage {
+            chunk["usage"] = usage;
+        }
+        format!("data: {}\n\n", chunk)
+    }
+
+    fn events(&self) -> Vec<String> {
+        match &self.reply {
+            MockReply::ToolCall { name, arguments } => {
+                let mut tool_call = self.tool_call(name, arguments);
+                tool_call["index"] = json!(0);
+                vec![self.chunk(json!({"role": "assistant", "tool_calls": [tool_call]}), Some("tool_calls"), Some(self.usage(&arguments.to_string())))]
+            },
+            MockReply::Text { content } | MockReply::TruncatedStream { content } => {
+                let chars: Vec<char> = content.chars().collect();
+                let mut events: Vec<String> = chars.chunks(STREAM_CHUNK_CHARS)
+                    .map(|piece| self.chunk(json!({"role": "assistant", "content": piece.iter().collect::<String>()}), None, None))
+                    .collect();
+                events.push(self.chunk(json!({"role": "assistant", "content": ""}), Some("stop"), Some(self.usage(content))));
+                events
+            },
+            MockReply::Error { .. } => Vec::new(),
+        }
+    }
+}
+
+async fn write_stream(stream: &mut TcpStream, state: &ServerState, reply: &Reply) -> io::Result<()> {
+    write_event_stream_head(stream).await?;
+
+    let mut events = reply.events();
+    let truncated = matches!(reply.reply, MockReply::TruncatedStream { .. });
+    if truncated {
+        events.truncate(events.len() / 2);
+    } else {
+        events.push("data: [DONE]\n\n".to_string());
+    }
+
+    for event in events {
+        if !state.chunk_latency.is_zero() {
+            tokio::time::sleep(state.chunk_latency).await;
+        }
+        stream.write_all(event.as_bytes()).await?;
+        stream.flush().await?;
+    }
+
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn test_mock_server() {
+        let api_key = "mockid.mocksecret".to_string();
+        let server = MockServer::new()
+            .with_api_key(api_key.clone())
+            .with_echo(true)
+            .with_reply(MockReply::Error { status: 429, code: "1302".to_string(), message: "rate limited".to_string() })
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let client = OpenGLM::new(api_key).with_base_url(server.base_url());
+        let request = || client.chat().completions().create()
+            .with_model(Model::Glm4Flash)
+            .add_message(ChatMessage::User("你好，世界".to_string()));
+
+        let err = request().send().await.unwrap_err();
+        assert!(matches!(err, Error::Api { status: 429, .. }));
+
+        let result = request().send().await.unwrap();
+        assert!(matches!(result.choices[0].message, ChatMessage::Assistant(ref content) if content == "你好，世界"));
+
+        server.push_reply(MockReply::TruncatedStream { content: "一二三四五六七八九十".to_string() });
+        let mut iter = request().stream().send().await.unwrap();
+        let err = loop {
+            match iter.next().await {
+                Ok(Some(_)) => continue,
+                Ok(None) => panic!("truncated stream should not finish"),
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(err, Error::StreamError));
+        assert!(!iter.accumulated().is_empty());
+
+        let unauthorized = OpenGLM::new("mockid.wrongsecret".to_string()).with_base_url(server.base_url());
+        let err = unauthorized.chat().completions().create()
+            .with_model(Model::Glm4Flash)
+            .add_message(ChatMessage::User("你好".to_string()))
+            .send().await.unwrap_err();
+        assert!(matches!(err, Error::Api { status: 401, .. }));
+
+        assert_eq!(server.requests().len(), 3);
+        server.shutdown();
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Model {
+    Glm4,
+    Glm4Plus,
+    Glm4Air,
+    Glm4AirX,
+    Glm4Flash,
+    Glm4Long,
+    Glm4V,
+    Glm4VPlus,
+    CodeGeeX4,
+    CharGlm3,
+    Embedding2,
+    Embedding3,
+    CogView3,
+    CogView3Plus,
+    CogVideoX,
+    /// 微调模型或SDK尚未收录的模型
+    Custom(String),
+}
+
+const KNOWN_MODELS: [Model; 15] = [
+    Model::Glm4,
+    Model::Glm4Plus,
+    Model::Glm4Air,
+    Model::Glm4AirX,
+    Model::Glm4Flash,
+    Model::Glm4Long,
+    Model::Glm4V,
+    Model::Glm4VPlus,
+    Model::CodeGeeX4,
+    Model::CharGlm3,
+    Model::Embedding2,
+    Model::Embedding3,
+    Model::CogView3,
+    Model::CogView3Plus,
+    Model::CogVideoX,
+];
+
+impl Model {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Model::Glm4 => "glm-4",
+            Model::Glm4Plus => "glm-4-plus",
+            Model::Glm4Air => "glm-4-air",
+            Model::Glm4AirX => "glm-4-airx",
+            Model::Glm4Flash => "glm-4-flash",
+            Model::Glm4Long => "glm-4-long",
+            Model::Glm4V => "glm-4v",
+            Model::Glm4VPlus => "glm-4v-plus",
+            Model::CodeGeeX4 => "codegeex-4",
+            Model::CharGlm3 => "charglm-3",
+            Model::Embedding2 => "embedding-2",
+            Model::Embedding3 => "embedding-3",
+            Model::CogView3 => "cogview-3",
+            Model::CogView3Plus => "cogview-3-plus",
+            Model::CogVideoX => "cogvideox",
+            Model::Custom(model) => model,
+        }
+    }
+
+    // 微调模型的id形如"glm-4-flash:1234::abcd"，能力与基础模型一致；无法识别时返回None
+    fn base(&self) -> Option<&Model> {
+        let Model::Custom(model) = self else {
+            return Some(self);
+        };
+
+        let base = model.split(':').next().unwrap_or_default();
+        KNOWN_MODELS.iter().find(|known| known.as_str() == base)
+    }
+
+    /// 模型能力，SDK未收录的模型无法判断，返回None
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        let base = self.base()?;
+        let max_output_tokens = match base {
+            Model::CodeGeeX4 => 32_768,
+            Model::Glm4V | Model::Glm4VPlus => 1_024,
+            Model::CharGlm3 => 2_048,
+            Model::Embedding2 | Model::Embedding3 | Model::CogView3 | Model::CogView3Plus | Model::CogVideoX => 0,
+            _ => 4_095,
+        };
+        let tools = matches!(base, Model::Glm4 | Model::Glm4Plus | Model::Glm4Air | Model::Glm4AirX | Model::Glm4Flash | Model::Glm4Long);
+
+        Some(Capabilities {
+            context_length: match base {
+                Model::Glm4Long => 1_000_000,
+                Model::Glm4AirX | Model::Glm4VPlus | Model::Embedding3 => 8_192,
+                Model::Glm4V => 2_048,
+                Model::CharGlm3 => 4_096,
+                Model::Embedding2 => 512,
+                Model::CogView3 | Model::CogView3Plus | Model::CogVideoX => 1_024,
+                _ => 128_000,
+            },
+            max_output_tokens,
+            chat: max_output_tokens > 0,
+            streaming: max_output_tokens > 0,
+            tools,
+            vision: matches!(base, Model::Glm4V | Model::Glm4VPlus),
+            web_search: tools,
+        })
+    }
+
+    /// 上下文长度（输入加输出），未收录的模型按glm-4估算
+    pub fn context_length(&self) -> usize {
+        self.capabilities().map_or(128_000, |capabilities| capabilities.context_length)
+    }
+
+    /// 单次最多输出的token数，非对话模型为0，未收录的模型按glm-4估算
+    pub fn max_output_tokens(&self) -> usize {
+        self.capabilities().map_or(4_095, |capabilities| capabilities.max_output_tokens)
+    }
+}
+
+/// 已收录模型的能力，用于在发送前检查请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// 上下文长度（输入加输出）
+    pub context_length: usize,
+    /// 单次最多输出的token数，非对话模型为0
+    pub max_output_tokens: usize,
+    /// 是否可以用于chat/completions接口
+    pub chat: bool,
+    /// 是否支持stream()流式输出
+    pub streaming: bool,
+    pub tools: bool,
+    pub vision: bool,
+    pub web_search: bool,
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for Model {
+    fn from(value: &str) -> Self {
+        KNOWN_MODELS.iter()
+            .find(|known| known.as_str() == value)
+            .cloned()
+            .unwrap_or_else(|| Model::Custom(value.to_string()))
+    }
+}
+
+impl From<String> for Model {
+    fn from(value: String) -> Self {
+        Model::from(value.as_str())
+    }
+}
+
+impl serde::Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl <'de> serde::Deserialize<'de> for Model {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let model = String::deserialize(deserializer)?;
+        Ok(Model::from(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_from_str() {
+        assert_eq!(Model::from("glm-4-flash"), Model::Glm4Flash);
+
+        let fine_tuned = Model::from("glm-4-flash:1234::abcd");
+        assert_eq!(fine_tuned, Model::Custom("glm-4-flash:1234::abcd".to_string()));
+        assert!(fine_tuned.capabilities().unwrap().tools);
+        assert!(!Model::Glm4V.capabilities().unwrap().tools);
+        assert!(!Model::Embedding3.capabilities().unwrap().chat);
+        assert!(Model::CharGlm3.capabilities().unwrap().streaming);
+        assert!(!Model::CogView3.capabilities().unwrap().streaming);
+    }
+
+    #[test]
+    fn test_unknown_model_capabilities() {
+        // 无法判断能力，不应当按glm-4限制
+        assert_eq!(Model::from("glm-5v").capabilities(), None);
+        assert_eq!(Model::from("my-model:1234").capabilities(), None);
+        assert_eq!(Model::from("glm-5v").context_length(), 128_000);
+    }
+}
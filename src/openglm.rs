@@ -1,17 +1,203 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::authen::Auth;
 use crate::chat::chat::Chat;
+use crate::cache::ResponseCache;
+use crate::fallback::FallbackPolicy;
+use crate::http::DEFAULT_BASE_URL;
+use crate::key_pool::{KeyLease, KeyPool};
+use crate::usage::UsageTracker;
+use crate::error::Result;
+use crate::limiter::RateLimiter;
+use crate::middleware::Middleware;
+use crate::transport::{HttpTransport, ReqwestTransport};
+use crate::timeout::Timeouts;
 
-pub struct OpenGLM {
+#[derive(Clone)]
+struct ClientConfig {
     api_key: String,
+    auth: Auth,
+    base_url: String,
+    timeouts: Timeouts,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    fallback: Option<Arc<FallbackPolicy>>,
+    key_pool: Option<Arc<KeyPool>>,
+    cache: Option<ResponseCache>,
+    usage_tracker: Option<Arc<UsageTracker>>,
+    #[cfg(feature = "tracing")]
+    log_content: bool,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    transport: Arc<dyn HttpTransport>,
+}
+
+#[derive(Clone)]
+pub struct OpenGLM {
+    config: Arc<ClientConfig>,
 }
 
 impl OpenGLM {
     pub fn new(api_key: String) -> Self {
         Self {
-            api_key,
+            config: Arc::new(ClientConfig {
+                api_key,
+                auth: Auth::Jwt,
+                base_url: DEFAULT_BASE_URL.to_string(),
+                timeouts: Timeouts::default(),
+                rate_limiter: None,
+                fallback: None,
+                key_pool: None,
+                cache: None,
+                usage_tracker: None,
+                #[cfg(feature = "tracing")]
+                log_content: false,
+                middlewares: Vec::new(),
+                transport: Arc::new(ReqwestTransport::new()),
+            }),
         }
     }
 
+    fn configure(self, f: impl FnOnce(&mut ClientConfig)) -> Self {
+        let mut config = (*self.config).clone();
+        f(&mut config);
+
+        Self {
+            config: Arc::new(config),
+        }
+    }
+
+    /// 接口地址，默认为https://open.bigmodel.cn/api/paas/v4，可指向代理或本地的mock服务
+    pub fn with_base_url(self, base_url: String) -> Self {
+        self.configure(|config| config.base_url = base_url.trim_end_matches('/').to_string())
+    }
+
+    /// 从发出请求到收到响应头的超时时间，包括建立连接，可被请求级设置覆盖。
+    ///
+    /// 非流式请求通常在生成完毕后才返回响应头，因此该时间需覆盖生成耗时。
+    pub fn with_headers_timeout(self, timeout: Duration) -> Self {
+        self.configure(|config| config.timeouts.headers = Some(timeout))
+    }
+
+    /// 建立连接（包括TLS握手）的超时时间，超时时返回TimeoutPhase::Connect。
+    ///
+    /// 连接由连接池复用，无法按请求设置；该方法将传输层替换为ReqwestTransport::with_connect_timeout，会覆盖with_transport的设置。
+    pub fn with_connect_timeout(self, timeout: Duration) -> Self {
+        self.with_transport(ReqwestTransport::with_connect_timeout(timeout))
+    }
+
+    /// 整个请求的超时时间，可被请求级设置覆盖
+    pub fn with_deadline(self, timeout: Duration) -> Self {
+        self.configure(|config| config.timeouts.deadline = Some(timeout))
+    }
+
+    /// 流式请求两次收到数据之间的超时时间，可被请求级设置覆盖
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        self.configure(|config| config.timeouts.idle = Some(timeout))
+    }
+
+    /// 所有经由该客户端（及其克隆）发出的请求共享同一个限流器
+    pub fn with_rate_limiter(self, rate_limiter: RateLimiter) -> Self {
+        self.configure(|config| config.rate_limiter = Some(Arc::new(rate_limiter)))
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.config.rate_limiter.as_deref()
+    }
+
+    /// 请求失败时按策略依次改用其他模型重试
+    pub fn with_fallback(self, policy: FallbackPolicy) -> Self {
+        self.configure(|config| config.fallback = Some(Arc::new(policy)))
+    }
+
+    pub(crate) fn fallback(&self) -> Option<&FallbackPolicy> {
+        self.config.fallback.as_deref()
+    }
+
+    /// 每次请求从密钥池中选择api_key，替代new时传入的api_key
+    pub fn with_key_pool(self, key_pool: KeyPool) -> Self {
+        self.configure(|config| config.key_pool = Some(Arc::new(key_pool)))
+    }
+
+    pub fn key_pool(&self) -> Option<&KeyPool> {
+        self.config.key_pool.as_deref()
+    }
+
+    pub(crate) fn acquire_key(&self) -> Result<Option<KeyLease>> {
+        self.config.key_pool.as_ref().map(KeyPool::acquire).transpose()
+    }
+
+    /// 缓存对话接口的结果，相同的请求不再发出
+    pub fn with_cache(self, cache: ResponseCache) -> Self {
+        self.configure(|config| config.cache = Some(cache))
+    }
+
+    pub(crate) fn cache(&self) -> Option<&ResponseCache> {
+        self.config.cache.as_ref()
+    }
+
+    /// 在tracing中以DEBUG级别记录对话内容，默认关闭；密钥和JWT始终脱敏
+    #[cfg(feature = "tracing")]
+    pub fn with_content_logging(self, log_content: bool) -> Self {
+        self.configure(|config| config.log_content = log_content)
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn logs_content(&self) -> bool {
+        self.config.log_content
+    }
+
+    /// 汇总该客户端（及其克隆）所有请求的用量和费用
+    pub fn with_usage_tracker(self, usage_tracker: UsageTracker) -> Self {
+        self.configure(|config| config.usage_tracker = Some(Arc::new(usage_tracker)))
+    }
+
+    pub fn usage_tracker(&self) -> Option<&UsageTracker> {
+        self.config.usage_tracker.as_deref()
+    }
+
+    pub(crate) fn usage_tracker_arc(&self) -> Option<Arc<UsageTracker>> {
+        self.config.usage_tracker.clone()
+    }
+
+    /// 追加中间件，先添加的位于外层，最先处理请求
+    pub fn with_middleware(self, middleware: impl Middleware + 'static) -> Self {
+        self.configure(|config| config.middlewares.push(Arc::new(middleware)))
+    }
+
+    /// 替换发送请求的底层实现，默认使用reqwest
+    pub fn with_transport(self, transport: impl HttpTransport + 'static) -> Self {
+        self.configure(|config| config.transport = Arc::new(transport))
+    }
+
+    pub(crate) fn transport(&self) -> &dyn HttpTransport {
+        self.config.transport.as_ref()
+    }
+
+    pub(crate) fn middlewares(&self) -> &[Arc<dyn Middleware>] {
+        &self.config.middlewares
+    }
+
+    pub(crate) fn api_key(&self) -> &str {
+        &self.config.api_key
+    }
+
+    /// 改为直接以api_key作为Bearer token，用于OpenAI兼容服务
+    pub(crate) fn with_bearer_auth(self) -> Self {
+        self.configure(|config| config.auth = Auth::Bearer)
+    }
+
+    pub(crate) fn auth(&self) -> Auth {
+        self.config.auth
+    }
+
+    pub(crate) fn base_url(&self) -> &str {
+        &self.config.base_url
+    }
+
+    pub(crate) fn timeouts(&self) -> Timeouts {
+        self.config.timeouts
+    }
+
     pub fn chat(&self) -> Chat {
-        Chat::new(self.api_key.clone())
+        Chat::new(self.clone())
     }
 }
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use crate::chat::completions::completions::CompletionsRequestBuilder;
+use crate::openglm::OpenGLM;
+
+/// 对话接口的提供方。GLM与OpenAI兼容服务共用ChatMessage、Tool和CompletionResult等类型，
+/// 业务代码只依赖该trait时，切换提供方只需修改配置。
+pub trait ChatProvider: Send + Sync {
+    /// 用于日志等场景的名称
+    fn name(&self) -> &str;
+
+    /// 创建请求，用法与client.chat().completions().create()相同
+    fn create(&self) -> CompletionsRequestBuilder;
+}
+
+impl ChatProvider for OpenGLM {
+    fn name(&self) -> &str {
+        "glm"
+    }
+
+    fn create(&self) -> CompletionsRequestBuilder {
+        self.chat().completions().create()
+    }
+}
+
+/// OpenAI兼容的chat/completions服务，如本地部署的vLLM、Ollama。
+///
+/// 请求经由内部的OpenGLM发送，中间件、限流、超时等设置同样适用，只是以api_key直接作为Bearer token，
+/// 且发送前只做通用的参数检查，不套用GLM的取值范围、request_id格式和模型能力限制。
+#[derive(Clone)]
+pub struct OpenAICompatible {
+    client: OpenGLM,
+}
+
+impl OpenAICompatible {
+    /// base_url为chat/completions的上一级路径，如http://localhost:8000/v1；api_key为空时不发送Authorization
+    pub fn new(base_url: String, api_key: String) -> Self {
+        Self {
+            client: OpenGLM::new(api_key).with_base_url(base_url).with_bearer_auth(),
+        }
+    }
+
+    /// 修改内部客户端的设置，如超时、中间件和HttpTransport
+    pub fn configure(self, f: impl FnOnce(OpenGLM) -> OpenGLM) -> Self {
+        Self {
+            client: f(self.client),
+        }
+    }
+
+    pub fn client(&self) -> &OpenGLM {
+        &self.client
+    }
+}
+
+impl ChatProvider for OpenAICompatible {
+    fn name(&self) -> &str {
+        "openai-compatible"
+    }
+
+    fn create(&self) -> CompletionsRequestBuilder {
+        self.client.chat().completions().create()
+    }
+}
+
+/// 可从配置文件反序列化的提供方设置，如{"provider": "openai_compatible", "base_url": "http://localhost:8000/v1"}
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    Glm {
+        api_key: String,
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    OpenaiCompatible {
+        base_url: String,
+        #[serde(default)]
+        api_key: String,
+    },
+}
+
+impl ProviderConfig {
+    pub fn build(self) -> Arc<dyn ChatProvider> {
+        match self {
+            ProviderConfig::Glm { api_key, base_url } => {
+                let client = OpenGLM::new(api_key);
+                Arc::new(match base_url {
+                    Some(base_url) => client.with_base_url(base_url),
+                    None => client,
+                })
+            },
+            ProviderConfig::OpenaiCompatible { base_url, api_key } => Arc::new(OpenAICompatible::new(base_url, api_key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // OpenAI格式的响应包含object、system_fingerprint等GLM没有的字段
+    #[tokio::test]
+    async fn test_openai_compatible() {
+        use crate::prelude::*;
+
+        let transport = Arc::new(MockTransport::new()
+            .with_response(MockResponse::json(&json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1711433468,
+                "model": "qwen2.5",
+                "system_fingerprint": "fp_1",
+                "choices": [{"index": 0, "finish_reason": "tool_calls", "logprobs": null, "message": {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"北京\"}"}},
+                ]}}],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+            })))
+            .with_response(MockResponse::sse([
+                json!({"id": "chatcmpl-2", "object": "chat.completion.chunk", "created": 1711433468, "model": "qwen2.5", "choices": [{"index": 0, "delta": {"role": "assistant", "content": "晴"}, "finish_reason": null}]}),
+                json!({"id": "chatcmpl-2", "object": "chat.completion.chunk", "created": 1711433468, "model": "qwen2.5", "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]}),
+            ])));
+        let provider: Arc<dyn ChatProvider> = Arc::new(OpenAICompatible::new("http://localhost:8000/v1/".to_string(), "sk-local".to_string())
+            .configure(|client| client.with_transport(transport.clone())));
+        // 不受GLM的取值范围、request_id格式和模型能力限制
+        let request = || provider.create()
+            .with_model("qwen2.5")
+            .with_temperature(1.5)
+            .with_max_tokens(8192)
+            .with_request_id("req-1".to_string())
+            .add_message(ChatMessage::User("北京天气如何？".to_string()));
+
+        let result = request().send().await.unwrap();
+        assert!(result.choices[0].finish_reason.is_tool_calls());
+        assert!(matches!(result.choices[0].message, ChatMessage::ToolCall(_)));
+
+        let mut iter = request().stream().send().await.unwrap();
+        while iter.next().await.unwrap().is_some() {}
+        assert!(matches!(iter.partial_message().unwrap(), ChatMessage::Assistant(ref content) if content == "晴"));
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].url, "http://localhost:8000/v1/chat/completions");
+        assert_eq!(requests[0].headers["authorization"], "Bearer sk-local");
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["temperature"], 1.5);
+    }
+
+    #[test]
+    fn test_provider_config() {
+        let config: ProviderConfig = serde_json::from_value(json!({"provider": "openai_compatible", "base_url": "http://localhost:11434/v1"})).unwrap();
+        assert_eq!(config.build().name(), "openai-compatible");
+
+        let config: ProviderConfig = serde_json::from_value(json!({"provider": "glm", "api_key": "id.secret"})).unwrap();
+        assert_eq!(config.build().name(), "glm");
+    }
+}
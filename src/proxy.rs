@@ -0,0 +1,505 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Instant};
+
+use bytes::Bytes;
+use serde_json::json;
+use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}, task::JoinHandle};
+
+use crate::chat::completions::completions::CompletionsRequestBuilder;
+use crate::chat::completions::result::{CompletionChoice, CompletionChoiceDelta, CompletionResult, FinishReason, Usage};
+use crate::chat::completions::RequestBuild;
+use crate::chat::message::{AssistantMessageDelta, ChatMessage};
+use crate::chat::tools::FunctionTool;
+use crate::error::Error;
+use crate::http::post_json;
+use crate::openglm::OpenGLM;
+use crate::send::Sendable;
+use crate::server::{read_request, write_event_stream_head, write_response};
+use crate::timeout::{with_timeout, TimeoutPhase};
+
+/// OpenAI格式的错误响应
+struct ProxyError {
+    status: u16,
+    ty: &'static str,
+    message: String,
+}
+
+impl ProxyError {
+    fn invalid_request(message: impl Into<String>) -> Self {
+        Self { status: 400, ty: "invalid_request_error", message: message.into() }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({"error": {"message": self.message, "type": self.ty, "code": null}})
+    }
+}
+
+impl From<Error> for ProxyError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Api { status, message, .. } => Self { status, ty: "api_error", message },
+            Error::Validation(e) => Self::invalid_request(e.to_string()),
+            Error::Timeout { .. } => Self { status: 504, ty: "timeout", message: e.to_string() },
+            e => Self { status: 502, ty: "api_error", message: e.to_string() },
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProxyError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::invalid_request(e.to_string())
+    }
+}
+
+type ProxyResult<T> = std::result::Result<T, ProxyError>;
+
+/// 接收OpenAI格式的/v1/chat/completions和/v1/embeddings请求，转换后使用GLM接口完成。
+///
+/// 设置了access_key时要求调用方携带其中之一作为Bearer token，否则不校验。
+/// 无法转换的参数（如n大于1、response_format为json_object）返回400，chunked编码的请求体返回411。
+/// temperature和top_p按OpenAI的取值范围接收，发送前收进GLM的范围。
+pub struct Proxy {
+    client: OpenGLM,
+    access_keys: Vec<String>,
+}
+
+impl Proxy {
+    pub fn new(client: OpenGLM) -> Self {
+        Self {
+            client,
+            access_keys: Vec::new(),
+        }
+    }
+
+    pub fn with_access_key(mut self, access_key: String) -> Self {
+        self.access_keys.push(access_key);
+        self
+    }
+
+    pub async fn bind(self, addr: &str) -> io::Result<ProxyHandle> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let proxy = Arc::new(self);
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let proxy = proxy.clone();
+                tokio::spawn(async move {
+                    let _ = proxy.handle_connection(stream).await;
+                });
+            }
+        });
+
+        Ok(ProxyHandle { addr, task })
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        let Some(request) = read_request(&mut stream).await? else {
+            return Ok(());
+        };
+
+        let authorized = self.access_keys.is_empty() || request.authorization.as_deref()
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .is_some_and(|key| self.access_keys.iter().any(|access_key| access_key == key));
+        if !authorized {
+            return write_error(&mut stream, &ProxyError { status: 401, ty: "invalid_request_error", message: "invalid api key".to_string() }).await;
+        }
+        if request.chunked {
+            return write_error(&mut stream, &ProxyError { status: 411, ty: "invalid_request_error", message: "chunked request body is not supported, send Content-Length".to_string() }).await;
+        }
+
+        let result = match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/v1/chat/completions") => self.chat_completions(&mut stream, &request.body).await,
+            ("POST", "/v1/embeddings") => self.embeddings(&mut stream, &request.body).await,
+            _ => Err(ProxyError { status: 404, ty: "invalid_request_error", message: format!("unknown path {}", request.path) }),
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => write_error(&mut stream, &e).await,
+        }
+    }
+
+    async fn chat_completions(&self, stream: &mut TcpStream, body: &[u8]) -> ProxyResult<()> {
+        let body: serde_json::Value = serde_json::from_slice(body)?;
+        let request = translate_request(&self.client, &body)?;
+
+        if body["stream"] != true {
+            let result = request.send().await?;
+            let body = serde_json::to_vec(&completion_to_openai(&result)?)?;
+            return write_response(stream, 200, "application/json", &body).await.map_err(io_error);
+        }
+
+        let mut iter = request.stream().send().await?;
+        write_event_stream_head(stream).await.map_err(io_error)?;
+        let mut first = true;
+        loop {
+            let event = match iter.next().await {
+                Ok(Some(result)) => chunk_to_openai(&result, std::mem::take(&mut first)),
+                Ok(None) => break,
+                // 响应头已发送，只能以事件的形式告知错误
+                Err(e) => ProxyError::from(e).to_json(),
+            };
+            let is_error = event.get("error").is_some();
+            stream.write_all(format!("data: {}\n\n", event).as_bytes()).await.map_err(io_error)?;
+            if is_error {
+                return stream.shutdown().await.map_err(io_error);
+            }
+        }
+
+        stream.write_all(b"data: [DONE]\n\n").await.map_err(io_error)?;
+        stream.shutdown().await.map_err(io_error)
+    }
+
+    /// 向量接口的请求和响应格式与OpenAI一致，签名后直接转发
+    async fn embeddings(&self, stream: &mut TcpStream, body: &[u8]) -> ProxyResult<()> {
+        let body: serde_json::Value = serde_json::from_slice(body)?;
+        let body = Bytes::from(serde_json::to_vec(&body)?);
+        let timeouts = self.client.timeouts();
+        let exchange = post_json(&self.client, "embeddings", body, &[], timeouts, timeouts.deadline_from(Instant::now()), None).await?;
+        let body = with_timeout(exchange.response.bytes(), None, exchange.deadline, TimeoutPhase::Deadline).await??;
+
+        let mut body: serde_json::Value = serde_json::from_slice(&body).map_err(|e| ProxyError::from(Error::SerdeError(e)))?;
+        if let Some(object) = body.as_object_mut() {
+            object.entry("object").or_insert_with(|| json!("list"));
+        }
+        write_response(stream, 200, "application/json", &serde_json::to_vec(&body)?).await.map_err(io_error)
+    }
+}
+
+pub struct ProxyHandle {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl ProxyHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// OpenAI客户端使用的base_url
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    pub fn shutdown(self) {
+        self.task.abort();
+    }
+
+    /// 一直运行直到服务停止
+    pub async fn wait(self) {
+        let _ = self.task.await;
+    }
+}
+
+fn io_error(e: io::Error) -> ProxyError {
+    ProxyError { status: 500, ty: "api_error", message: e.to_string() }
+}
+
+async fn write_error(stream: &mut TcpStream, error: &ProxyError) -> io::Result<()> {
+    let body = error.to_json().to_string();
+    write_response(stream, error.status, "application/json", body.as_bytes()).await
+}
+
+/// 将OpenAI格式的消息调整为ChatMessage能解析的形式
+fn translate_message(message: &serde_json::Value) -> ProxyResult<ChatMessage> {
+    let mut message = message.as_object().cloned().ok_or_else(|| ProxyError::invalid_request("message must be an object"))?;
+    message.remove("name");
+    if message.get("role").is_some_and(|role| role == "developer") {
+        message.insert("role".to_string(), json!("system"));
+    }
+
+    let is_user = message.get("role").is_some_and(|role| role == "user");
+    match message.get("content") {
+        // 带工具调用的assistant消息只保留tool_calls
+        _ if message.contains_key("tool_calls") => {
+            message.remove("content");
+        },
+        // 只有用户消息支持多段内容，其他角色将文本段拼接起来
+        Some(serde_json::Value::Array(parts)) if !is_user => {
+            let text = parts.iter().filter_map(|part| part["text"].as_str()).collect::<String>();
+            message.insert("content".to_string(), json!(text));
+        },
+        _ => {},
+    }
+
+    Ok(serde_json::from_value(serde_json::Value::Object(message))?)
+}
+
+/// 检查无法转换的OpenAI参数，取默认值时视为未设置，其余一律拒绝而不是静默丢弃
+fn check_unsupported(body: &serde_json::Value) -> ProxyResult<()> {
+    for (name, value) in body.as_object().into_iter().flatten() {
+        let supported = match name.as_str() {
+            "model" | "messages" | "temperature" | "top_p" | "max_tokens" | "max_completion_tokens"
+                | "stop" | "user" | "tools" | "tool_choice" | "stream" | "stream_options" => true,
+            "n" => value == 1,
+            "response_format" => value["type"] == "text",
+            "logprobs" => value == false,
+            "parallel_tool_calls" => value == true,
+            "presence_penalty" | "frequency_penalty" => value.as_f64() == Some(0.0),
+            _ => false,
+        };
+        if !supported && !value.is_null() {
+            return Err(ProxyError::invalid_request(format!("unsupported parameter {}: {}", name, value)));
+        }
+    }
+    Ok(())
+}
+
+fn translate_request(client: &OpenGLM, body: &serde_json::Value) -> ProxyResult<CompletionsRequestBuilder> {
+    check_unsupported(body)?;
+    let model = body["model"].as_str().ok_or_else(|| ProxyError::invalid_request("model is required"))?;
+    let messages = body["messages"].as_array().ok_or_else(|| ProxyError::invalid_request("messages is required"))?;
+    let messages = messages.iter().map(translate_message).collect::<ProxyResult<Vec<_>>>()?;
+
+    let mut request = client.chat().completions().create()
+        .with_model(model)
+        .with_messages(messages);
+
+    if let Some(temperature) = body["temperature"].as_f64() {
+        request = request.with_temperature(glm_temperature(temperature as f32));
+    }
+    if let Some(top_p) = body["top_p"].as_f64() {
+        request = request.with_top_p(glm_top_p(top_p as f32));
+    }
+    if let Some(max_tokens) = body["max_completion_tokens"].as_i64().or(body["max_tokens"].as_i64()) {
+        request = request.with_max_tokens(max_tokens as i32);
+    }
+    match &body["stop"] {
+        serde_json::Value::String(stop) => request = request.with_stop(vec![stop.clone()]),
+        serde_json::Value::Array(stop) => request = request.with_stop(stop.iter().filter_map(|stop| stop.as_str().map(str::to_string)).collect()),
+        _ => {},
+    }
+    if let Some(user) = body["user"].as_str() {
+        request = request.with_extra_body("user_id".to_string(), json!(user));
+    }
+
+    for tool in body["tools"].as_array().map(Vec::as_slice).unwrap_or_default() {
+        if tool["type"] != "function" {
+            return Err(ProxyError::invalid_request(format!("unsupported tool type {}", tool["type"])));
+        }
+        let function = &tool["function"];
+        request = request.bind_function(FunctionTool {
+            name: function["name"].as_str().ok_or_else(|| ProxyError::invalid_request("function name is required"))?.to_string(),
+            description: function["description"].as_str().unwrap_or_default().to_string(),
+            parameters: function.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+        });
+    }
+    match &body["tool_choice"] {
+        serde_json::Value::Null => {},
+        serde_json::Value::String(tool_choice) => request = request.with_tool_choice(tool_choice.clone()),
+        _ => return Err(ProxyError::invalid_request("only string tool_choice is supported")),
+    }
+
+    Ok(request)
+}
+
+/// OpenAI的temperature取值为[0, 2]，超出GLM上限1的部分按1处理；不在OpenAI范围内的值原样保留，由参数检查拒绝
+fn glm_temperature(temperature: f32) -> f32 {
+    if (1.0..=2.0).contains(&temperature) { 1.0 } else { temperature }
+}
+
+/// OpenAI的top_p取值为[0, 1]，GLM要求在(0, 1)之间，两端收进开区间
+fn glm_top_p(top_p: f32) -> f32 {
+    if (0.0..=1.0).contains(&top_p) { top_p.clamp(0.01, 0.99) } else { top_p }
+}
+
+/// GLM特有的结束原因没有对应的OpenAI取值，内容审核映射为content_filter，其余为null
+fn finish_reason_to_openai(finish_reason: &FinishReason) -> serde_json::Value {
+    match finish_reason {
+        FinishReason::Stop | FinishReason::ToolCalls | FinishReason::Length => json!(finish_reason.as_str()),
+        reason if reason.was_filtered() => json!("content_filter"),
+        FinishReason::Other(reason) if reason == "function_call" => json!(reason),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn usage_to_openai(usage: &Usage) -> serde_json::Value {
+    json!({
+        "prompt_tokens": usage.prompt_tokens,
+        "completion_tokens": usage.completion_tokens,
+        "total_tokens": usage.total_tokens,
+    })
+}
+
+fn completion_to_openai(result: &CompletionResult<CompletionChoice>) -> ProxyResult<serde_json::Value> {
+    let choices = result.choices.iter().map(|choice| {
+        let mut message = serde_json::to_value(&choice.message)?;
+        if let ChatMessage::ToolCall(_) = choice.message {
+            message["content"] = serde_json::Value::Null;
+        }
+        Ok(json!({"index": choice.index, "message": message, "finish_reason": finish_reason_to_openai(&choice.finish_reason)}))
+    }).collect::<ProxyResult<Vec<_>>>()?;
+
+    Ok(json!({
+        "id": result.id,
+        "object": "chat.completion",
+        "created": result.created,
+        "model": result.model,
+        "choices": choices,
+        "usage": result.usage.as_ref().map(usage_to_openai),
+    }))
+}
+
+fn delta_to_openai(delta: &AssistantMessageDelta, first: bool) -> serde_json::Value {
+    let mut value = match delta {
+        AssistantMessageDelta::Content(content) => json!({"content": content}),
+        AssistantMessageDelta::ToolCall(tool_calls) => {
+            let tool_calls: Vec<_> = tool_calls.iter().enumerate().map(|(position, tool_call)| {
+                let mut value = json!({"index": tool_call.index.unwrap_or(position as i32)});
+                if let Some(id) = &tool_call.id {
+                    value["id"] = json!(id);
+                }
+                if let Some(ty) = &tool_call.ty {
+                    value["type"] = json!(ty);
+                }
+                if let Some(function) = &tool_call.function {
+                    value["function"] = json!({});
+                    if let Some(name) = &function.name {
+                        value["function"]["name"] = json!(name);
+                    }
+                    if let Some(arguments) = &function.arguments {
+                        value["function"]["arguments"] = json!(arguments);
+                    }
+                }
+                value
+            }).collect();
+            json!({"tool_calls": tool_calls})
+        },
+    };
+    if first {
+        value["role"] = json!("assistant");
+    }
+    value
+}
+
+fn chunk_to_openai(result: &CompletionResult<CompletionChoiceDelta>, first: bool) -> serde_json::Value {
+    let choices: Vec<_> = result.choices.iter().map(|choice| json!({
+        "index": choice.index,
+        "delta": delta_to_openai(&choice.delta, first),
+        "finish_reason": choice.finish_reason.as_ref().map(finish_reason_to_openai),
+    })).collect();
+
+    let mut chunk = json!({
+        "id": result.id,
+        "object": "chat.completion.chunk",
+        "created": result.created,
+        "model": result.model,
+        "choices": choices,
+    });
+    if let Some(usage) = &result.usage {
+        chunk["usage"] = usage_to_openai(usage);
+    }
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::{MockResponse, MockTransport};
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| json!({
+            "id": "2", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": finish_reason, "delta": delta}],
+        });
+        let transport = Arc::new(MockTransport::new()
+            .with_response(MockResponse::json(&json!({
+                "id": "1", "created": 1711433468, "model": "glm-4",
+                "choices": [{"index": 0, "finish_reason": "tool_calls", "message": {"role": "assistant", "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"北京\"}"}},
+                ]}}],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+            })))
+            .with_response(MockResponse::sse([
+                chunk(json!({"role": "assistant", "content": "晴"}), None),
+                chunk(json!({"role": "assistant", "content": ""}), Some("stop")),
+            ]))
+            .with_response(MockResponse::json(&json!({"model": "embedding-3", "data": [{"index": 0, "object": "embedding", "embedding": [0.1, 0.2]}]})))
+            .with_response(MockResponse::new(reqwest::StatusCode::TOO_MANY_REQUESTS, r#"{"error":{"code":"1302","message":"rate limited"}}"#))
+            .with_response(MockResponse::json(&json!({
+                "id": "3", "created": 1711433468, "model": "glm-4",
+                "choices": [{"index": 0, "finish_reason": "sensitive", "message": {"role": "assistant", "content": ""}}],
+            })))
+            .with_response(MockResponse::sse([
+                chunk(json!({"role": "assistant", "content": "晴"}), None),
+                chunk(json!({"role": "assistant", "content": ""}), Some("network_error")),
+            ])));
+        let client = OpenGLM::new("1111111111111111111111.xxxxxxx".to_string()).with_transport(transport.clone());
+        let proxy = Proxy::new(client).with_access_key("sk-test".to_string()).bind("127.0.0.1:0").await.unwrap();
+
+        let http = reqwest::Client::new();
+        let post = |path: &str, body: serde_json::Value| http.post(format!("{}{}", proxy.base_url(), path)).bearer_auth("sk-test").json(&body).send();
+        let messages = json!([
+            {"role": "developer", "content": "你是天气助手"},
+            {"role": "user", "content": "北京天气如何？", "name": "alice"},
+        ]);
+
+        let tools = json!([{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}]);
+        let response: serde_json::Value = post("/chat/completions", json!({"model": "glm-4", "messages": messages, "tools": tools}))
+            .await.unwrap().json().await.unwrap();
+        assert_eq!(response["object"], "chat.completion");
+        assert_eq!(response["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(response["choices"][0]["message"]["content"], serde_json::Value::Null);
+        assert_eq!(response["choices"][0]["message"]["tool_calls"][0]["function"]["name"], "get_weather");
+        assert_eq!(response["usage"]["total_tokens"], 15);
+
+        let body: serde_json::Value = serde_json::from_slice(&transport.requests()[0].body).unwrap();
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["tools"][0]["function"]["name"], "get_weather");
+
+        let events = post("/chat/completions", json!({"model": "glm-4", "messages": messages, "stream": true}))
+            .await.unwrap().text().await.unwrap();
+        let events: Vec<&str> = events.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+        assert_eq!(events.len(), 3);
+        let first: serde_json::Value = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(first["object"], "chat.completion.chunk");
+        assert_eq!(first["choices"][0]["delta"], json!({"role": "assistant", "content": "晴"}));
+        let last: serde_json::Value = serde_json::from_str(events[1]).unwrap();
+        assert_eq!(last["choices"][0]["finish_reason"], "stop");
+        assert_eq!(events[2], "[DONE]");
+
+        let response: serde_json::Value = post("/embeddings", json!({"model": "embedding-3", "input": "你好"}))
+            .await.unwrap().json().await.unwrap();
+        assert_eq!(response["object"], "list");
+        assert!(transport.requests()[2].url.ends_with("/embeddings"));
+
+        let response = post("/chat/completions", json!({"model": "glm-4", "messages": messages})).await.unwrap();
+        assert_eq!(response.status(), 429);
+        let response: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(response["error"]["message"], "rate limited");
+
+        let response = http.post(format!("{}/chat/completions", proxy.base_url())).json(&json!({})).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        // 无法转换的参数返回400，不转发
+        let response = post("/chat/completions", json!({"model": "glm-4", "messages": messages, "n": 2})).await.unwrap();
+        assert_eq!(response.status(), 400);
+        let response = post("/chat/completions", json!({"model": "glm-4", "messages": messages, "response_format": {"type": "json_object"}})).await.unwrap();
+        assert_eq!(response.status(), 400);
+
+        let mut socket = TcpStream::connect(proxy.addr()).await.unwrap();
+        socket.write_all(b"POST /v1/chat/completions HTTP/1.1\r\nAuthorization: Bearer sk-test\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut socket, &mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 411"));
+        assert_eq!(transport.requests().len(), 4);
+
+        // OpenAI的默认取值收进GLM的范围，GLM特有的结束原因映射为OpenAI取值
+        let response: serde_json::Value = post("/chat/completions", json!({"model": "glm-4", "messages": messages, "top_p": 1, "temperature": 1.5}))
+            .await.unwrap().json().await.unwrap();
+        assert_eq!(response["choices"][0]["finish_reason"], "content_filter");
+        let body: serde_json::Value = serde_json::from_slice(&transport.requests()[4].body).unwrap();
+        assert_eq!(body["temperature"], 1.0);
+        assert_eq!(body["top_p"].as_f64().unwrap() as f32, 0.99);
+
+        let events = post("/chat/completions", json!({"model": "glm-4", "messages": messages, "stream": true}))
+            .await.unwrap().text().await.unwrap();
+        let events: Vec<&str> = events.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+        let last: serde_json::Value = serde_json::from_str(events[1]).unwrap();
+        assert!(last["choices"][0].get("finish_reason").is_some_and(serde_json::Value::is_null));
+        proxy.shutdown();
+    }
+}
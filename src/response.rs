@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use reqwest::{header::HeaderMap, StatusCode};
+
+use crate::fallback::FallbackAttempt;
+use crate::model::Model;
+
+/// HTTP响应的状态码、响应头和耗时
+#[derive(Debug, Clone)]
+pub struct ResponseMeta {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// 从发出请求到收到响应头的耗时，不包括读取响应体；流式请求的生成过程不计入
+    pub elapsed: Duration,
+    /// 实际应答的模型
+    pub model: Option<Model>,
+    /// 触发降级前失败的尝试，按顺序排列
+    pub fallbacks: Vec<FallbackAttempt>,
+    /// 结果来自ResponseCache，没有发出请求
+    pub cached: bool,
+}
+
+impl ResponseMeta {
+    pub(crate) fn cached(model: Option<Model>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            elapsed: Duration::ZERO,
+            model,
+            fallbacks: Vec::new(),
+            cached: true,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+/// send_with_response的返回值：解析结果以及原始响应
+#[derive(Debug)]
+pub struct Response<T> {
+    pub meta: ResponseMeta,
+    /// 原始响应体，流式请求为空，可通过CompletionDeltaIter::last_line查看原始数据
+    pub body: Bytes,
+    pub value: T,
+}
+
+impl <T> Response<T> {
+    pub fn status(&self) -> StatusCode {
+        self.meta.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.meta.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.meta.header(name)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.meta.elapsed
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+}
@@ -1,6 +1,8 @@
-use std::future::Future;
+use std::{future::Future, pin::Pin};
 use crate::error::Result;
 
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
 pub trait Sendable {
     type Output;
 
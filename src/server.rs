@@ -0,0 +1,84 @@
+use std::io;
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+// mock-server和proxy共用的最小HTTP/1.1服务端实现，每个连接只处理一个请求
+
+const MAX_REQUEST_BYTES: usize = 16 * 1024 * 1024;
+
+pub(crate) struct ParsedRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) authorization: Option<String>,
+    /// 请求体使用了Transfer-Encoding: chunked，此时不读取请求体，body为空
+    pub(crate) chunked: bool,
+    pub(crate) body: Vec<u8>,
+}
+
+pub(crate) async fn read_request(stream: &mut TcpStream) -> io::Result<Option<ParsedRequest>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let header_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break pos;
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 || buffer.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse().unwrap_or(0),
+            "authorization" => authorization = Some(value.trim().to_string()),
+            "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+            _ => {},
+        }
+    }
+
+    if chunked {
+        return Ok(Some(ParsedRequest { method, path, authorization, chunked, body: Vec::new() }));
+    }
+
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < content_length.min(MAX_REQUEST_BYTES) {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(Some(ParsedRequest { method, path, authorization, chunked, body }))
+}
+
+pub(crate) async fn write_response(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let reason = reqwest::StatusCode::from_u16(status).ok().and_then(|status| status.canonical_reason()).unwrap_or("");
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, reason, content_type, body.len(),
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
+
+/// 写入SSE响应头，之后由调用方逐条写入事件并关闭连接
+pub(crate) async fn write_event_stream_head(stream: &mut TcpStream) -> io::Result<()> {
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes()).await
+}
@@ -0,0 +1,377 @@
+// 开启tracing特性时为每个请求创建span，字段命名遵循OpenTelemetry GenAI语义约定；
+// 未开启时RequestSpan的方法均为空实现。对话内容默认不记录，密钥和JWT始终脱敏。
+
+#[cfg(feature = "tracing")]
+pub(crate) use enabled::*;
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use std::{future::Future, time::Instant};
+
+    use tracing::{field::Empty, Instrument, Level};
+
+    use crate::authen::{parse_api_key, Auth};
+    use crate::chat::completions::request_inner::RequestInner;
+    use crate::chat::completions::result::{CompletionChoice, CompletionChoiceDelta, CompletionResult, Usage};
+    use crate::chat::message::ChatMessage;
+    use crate::error::Error;
+    use crate::model::Model;
+    use crate::openglm::OpenGLM;
+    use crate::response::{Response, ResponseMeta};
+
+    const REDACTED: &str = "[REDACTED]";
+
+    pub(crate) struct RequestSpan {
+        span: tracing::Span,
+        started: Instant,
+        first_token: bool,
+        content: bool,
+        secrets: Vec<String>,
+    }
+
+    impl RequestSpan {
+        pub(crate) fn none() -> Self {
+            Self {
+                span: tracing::Span::none(),
+                started: Instant::now(),
+                first_token: false,
+                content: false,
+                secrets: Vec::new(),
+            }
+        }
+
+        pub(crate) fn start(client: &OpenGLM, inner: &RequestInner, stream: bool) -> Self {
+            let model = inner.model().map(Model::to_string).unwrap_or_default();
+            let system = match client.auth() {
+                Auth::Jwt => "zhipu",
+                Auth::Bearer => "openai_compatible",
+            };
+            let address = client.base_url().split("://").nth(1).and_then(|rest| rest.split(['/', ':']).next()).unwrap_or_default();
+            let span = tracing::info_span!(
+                "chat",
+                otel.name = %format!("chat {}", model),
+                gen_ai.operation.name = "chat",
+                gen_ai.system = system,
+                gen_ai.request.model = %model,
+                gen_ai.request.temperature = inner.temperature(),
+                gen_ai.request.top_p = inner.top_p(),
+                gen_ai.request.max_tokens = inner.max_tokens(),
+                gen_ai.response.id = Empty,
+                gen_ai.response.model = Empty,
+                gen_ai.response.finish_reasons = Empty,
+                gen_ai.usage.input_tokens = Empty,
+                gen_ai.usage.output_tokens = Empty,
+                server.address = address,
+                error.type = Empty,
+                openglm.request_id = inner.request_id(),
+                openglm.stream = stream,
+                openglm.cached = Empty,
+                openglm.attempts = Empty,
+                openglm.latency_ms = Empty,
+                openglm.time_to_first_token_ms = Empty,
+            );
+
+            let request = Self { span, started: Instant::now(), first_token: false, content: client.logs_content(), secrets: secrets(client) };
+            if request.content {
+                for message in inner.messages() {
+                    request.content_event("gen_ai.prompt", message);
+                }
+            }
+            request
+        }
+
+        pub(crate) fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+            future.instrument(self.span.clone())
+        }
+
+        fn elapsed_ms(&self) -> u64 {
+            self.started.elapsed().as_millis() as u64
+        }
+
+        fn content_event(&self, name: &str, message: &ChatMessage) {
+            let content = serde_json::to_string(message).unwrap_or_default();
+            tracing::event!(parent: &self.span, Level::DEBUG, event.name = name, content = %redact(&content, &self.secrets));
+        }
+
+        fn record_usage(&self, usage: &Usage) {
+            self.span.record("gen_ai.usage.input_tokens", usage.prompt_tokens);
+            self.span.record("gen_ai.usage.output_tokens", usage.completion_tokens);
+        }
+
+        pub(crate) fn record_meta(&self, meta: &ResponseMeta) {
+            self.span.record("openglm.cached", meta.cached);
+            self.span.record("openglm.attempts", meta.fallbacks.len() + 1);
+            // 降级后实际应答的模型，稍后会被接口返回的model覆盖
+            if let Some(model) = &meta.model {
+                self.span.record("gen_ai.response.model", model.as_str());
+            }
+        }
+
+        pub(crate) fn record_response(&self, response: &Response<CompletionResult<CompletionChoice>>) {
+            self.record_meta(&response.meta);
+            let result = &response.value;
+            self.span.record("gen_ai.response.id", result.id.as_str());
+            self.span.record("gen_ai.response.model", result.model.as_str());
+            let finish_reasons: Vec<&str> = result.choices.iter().map(|choice| choice.finish_reason.as_str()).collect();
+            self.span.record("gen_ai.response.finish_reasons", finish_reasons.join(",").as_str());
+            if let Some(usage) = &result.usage {
+                self.record_usage(usage);
+            }
+            self.span.record("openglm.latency_ms", self.elapsed_ms());
+
+            if self.content {
+                for choice in &result.choices {
+                    self.content_event("gen_ai.completion", &choice.message);
+                }
+            }
+        }
+
+        pub(crate) fn record_chunk(&mut self, result: &CompletionResult<CompletionChoiceDelta>) {
+            if !self.first_token {
+                self.first_token = true;
+                self.span.record("openglm.time_to_first_token_ms", self.elapsed_ms());
+                self.span.record("gen_ai.response.id", result.id.as_str());
+                self.span.record("gen_ai.response.model", result.model.as_str());
+            }
+            if let Some(finish_reason) = result.choices.iter().find_map(|choice| choice.finish_reason.as_ref()) {
+                self.span.record("gen_ai.response.finish_reasons", finish_reason.as_str());
+            }
+            if let Some(usage) = &result.usage {
+                self.record_usage(usage);
+            }
+        }
+
+        /// 流结束或被取消，message为第一个choice已生成的内容
+        pub(crate) fn record_stream_end(&self, message: Option<&ChatMessage>) {
+            self.span.record("openglm.latency_ms", self.elapsed_ms());
+            if let (true, Some(message)) = (self.content, message) {
+                self.content_event("gen_ai.completion", message);
+            }
+        }
+
+        pub(crate) fn record_error(&self, error: &Error) {
+            let ty = match error {
+                Error::Api { status, .. } => status.to_string(),
+                Error::Timeout { .. } => "timeout".to_string(),
+                Error::Cancelled => "cancelled".to_string(),
+                Error::Validation(_) => "validation".to_string(),
+                _ => "error".to_string(),
+            };
+            self.span.record("error.type", ty.as_str());
+            self.span.record("openglm.latency_ms", self.elapsed_ms());
+            tracing::event!(parent: &self.span, Level::WARN, error = %redact(&error.to_string(), &self.secrets));
+        }
+    }
+
+    /// 降级前一次失败的尝试，记录在当前span中
+    pub(crate) fn attempt_failed(client: &OpenGLM, model: &Model, error: &Error) {
+        tracing::event!(Level::WARN, openglm.attempt.model = %model, error = %redact(&error.to_string(), &secrets(client)), "fallback");
+    }
+
+    // 客户端使用的全部密钥，日志中需要脱敏
+    fn secrets(client: &OpenGLM) -> Vec<String> {
+        let mut secrets = vec![client.api_key().to_string()];
+        if let Some(key_pool) = client.key_pool() {
+            secrets.extend(key_pool.api_keys().map(str::to_string));
+        }
+        secrets
+    }
+
+    fn is_token_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+    }
+
+    /// 移除文本中的api_key、其secret部分、Bearer token和JWT
+    pub(crate) fn redact(text: &str, secrets: &[String]) -> String {
+        let mut text = text.to_string();
+        for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+            text = text.replace(secret.as_str(), REDACTED);
+            if let Ok((_, secret)) = parse_api_key(secret) {
+                if !secret.is_empty() {
+                    text = text.replace(secret, REDACTED);
+                }
+            }
+        }
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find(|c: char| is_token_char(c)) {
+            redacted.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c: char| !is_token_char(c)).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let is_jwt = word.starts_with("eyJ") && word.matches('.').count() == 2;
+            let after_bearer = redacted.ends_with("Bearer ");
+            redacted.push_str(if is_jwt || after_bearer { REDACTED } else { word });
+            rest = &rest[end..];
+        }
+        redacted.push_str(rest);
+        redacted
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_redact() {
+            let jwt = crate::authen::generate("id.secret").unwrap();
+            let secrets = ["id.secret".to_string()];
+            let text = format!("key id.secret, s=secret, Authorization: Bearer sk-123 token {}", jwt);
+            assert_eq!(
+                redact(&text, &secrets),
+                "key [REDACTED], s=[REDACTED], Authorization: Bearer [REDACTED] token [REDACTED]",
+            );
+        }
+
+        type Fields = std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>;
+
+        struct Collector {
+            fields: Fields,
+            next_id: std::sync::atomic::AtomicU64,
+        }
+
+        struct Visitor<'a>(&'a Fields);
+
+        impl tracing::field::Visit for Visitor<'_> {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                self.0.lock().unwrap().push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+
+        impl tracing::Subscriber for Collector {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+                span.record(&mut Visitor(&self.fields));
+                tracing::span::Id::from_u64(self.next_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+            }
+
+            fn record(&self, _: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+                values.record(&mut Visitor(&self.fields));
+            }
+
+            fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+            fn event(&self, event: &tracing::Event<'_>) {
+                event.record(&mut Visitor(&self.fields));
+            }
+
+            fn enter(&self, _: &tracing::span::Id) {}
+
+            fn exit(&self, _: &tracing::span::Id) {}
+        }
+
+        #[tokio::test]
+        async fn test_request_span() {
+            use crate::prelude::*;
+
+            let fields = Fields::default();
+            let _guard = tracing::subscriber::set_default(Collector { fields: fields.clone(), next_id: 1.into() });
+
+            let chunk = |content: &str, finish_reason: Option<&str>| serde_json::json!({
+                "id": "2", "created": 1711433468, "model": "glm-4",
+                "choices": [{"index": 0, "finish_reason": finish_reason, "delta": {"role": "assistant", "content": content}}],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12},
+            });
+            let transport = MockTransport::new()
+                .with_response(MockResponse::new(reqwest::StatusCode::UNAUTHORIZED, r#"{"error":{"code":"1000","message":"invalid token Bearer abc.def"}}"#))
+                .with_response(MockResponse::sse([chunk("你好", None), chunk("", Some("stop"))]));
+            let client = OpenGLM::new("mockid.mocksecret".to_string())
+                .with_transport(transport)
+                .with_content_logging(true);
+            let request = || client.chat().completions().create()
+                .with_model(Model::Glm4)
+                .add_message(ChatMessage::User("我的密钥是mockid.mocksecret".to_string()));
+
+            assert!(request().send().await.is_err());
+            let mut iter = request().stream().send().await.unwrap();
+            while iter.next().await.unwrap().is_some() {}
+
+            let fields = fields.lock().unwrap().clone();
+            let value = |name: &str| fields.iter().rev().find(|(field, _)| field == name).map(|(_, value)| value.clone());
+            assert_eq!(value("error.type").as_deref(), Some("\"401\""));
+            assert_eq!(value("gen_ai.usage.input_tokens").as_deref(), Some("10"));
+            assert_eq!(value("gen_ai.response.finish_reasons").as_deref(), Some("\"stop\""));
+            assert!(value("openglm.time_to_first_token_ms").is_some());
+            assert!(value("openglm.latency_ms").is_some());
+            assert!(fields.iter().any(|(field, value)| field == "content" && value.contains("你好")));
+            assert!(fields.iter().all(|(_, value)| !value.contains("mocksecret") && !value.contains("abc.def")));
+        }
+
+        #[tokio::test]
+        async fn test_fallback_span() {
+            use crate::prelude::*;
+
+            let fields = Fields::default();
+            let _guard = tracing::subscriber::set_default(Collector { fields: fields.clone(), next_id: 1.into() });
+
+            let transport = MockTransport::new()
+                .with_response(MockResponse::new(reqwest::StatusCode::TOO_MANY_REQUESTS, r#"{"error":{"code":"1302","message":"key mockid.mocksecret is rate limited"}}"#))
+                .with_response(MockResponse::json(&serde_json::json!({
+                    "id": "1", "created": 1711433468, "model": "glm-4-air",
+                    "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+                })));
+            let client = OpenGLM::new("mockid.mocksecret".to_string())
+                .with_transport(transport)
+                .with_fallback(FallbackPolicy::new([Model::Glm4Plus, Model::Glm4Air]));
+            client.chat().completions().create()
+                .with_model(Model::Glm4Plus)
+                .add_message(ChatMessage::User("你好".to_string()))
+                .send().await.unwrap();
+
+            // request.model保持为请求的模型，应答的模型记录在response.model中
+            let fields = fields.lock().unwrap().clone();
+            let values = |name: &str| fields.iter().filter(|(field, _)| field == name).map(|(_, value)| value.clone()).collect::<Vec<_>>();
+            assert_eq!(values("gen_ai.request.model"), ["glm-4-plus"]);
+            assert_eq!(values("gen_ai.response.model").last().map(String::as_str), Some("\"glm-4-air\""));
+            assert_eq!(values("openglm.attempt.model"), ["glm-4-plus"]);
+            assert!(fields.iter().all(|(_, value)| !value.contains("mocksecret")));
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use std::future::Future;
+
+    use crate::chat::completions::request_inner::RequestInner;
+    use crate::chat::completions::result::{CompletionChoice, CompletionChoiceDelta, CompletionResult};
+    use crate::chat::message::ChatMessage;
+    use crate::error::Error;
+    use crate::model::Model;
+    use crate::openglm::OpenGLM;
+    use crate::response::{Response, ResponseMeta};
+
+    pub(crate) struct RequestSpan;
+
+    impl RequestSpan {
+        pub(crate) fn none() -> Self {
+            Self
+        }
+
+        pub(crate) fn start(_: &OpenGLM, _: &RequestInner, _: bool) -> Self {
+            Self
+        }
+
+        pub(crate) fn instrument<F: Future>(&self, future: F) -> F {
+            future
+        }
+
+        pub(crate) fn record_meta(&self, _: &ResponseMeta) {}
+
+        pub(crate) fn record_response(&self, _: &Response<CompletionResult<CompletionChoice>>) {}
+
+        pub(crate) fn record_chunk(&mut self, _: &CompletionResult<CompletionChoiceDelta>) {}
+
+        pub(crate) fn record_stream_end(&self, _: Option<&ChatMessage>) {}
+
+        pub(crate) fn record_error(&self, _: &Error) {}
+    }
+
+    pub(crate) fn attempt_failed(_: &OpenGLM, _: &Model, _: &Error) {}
+}
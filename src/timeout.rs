@@ -0,0 +1,83 @@
+use std::{fmt, future::Future, time::{Duration, Instant}};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// 建立连接（包括TLS握手），见ReqwestTransport::with_connect_timeout
+    Connect,
+    /// 从发出请求到收到响应头，包括建立连接；非流式请求通常在生成完毕后才返回响应头
+    Headers,
+    /// 整个请求（流式请求包括读取全部数据）的截止时间
+    Deadline,
+    /// 流式请求两次收到数据之间的间隔
+    Idle,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutPhase::Connect => write!(f, "connect"),
+            TimeoutPhase::Headers => write!(f, "headers"),
+            TimeoutPhase::Deadline => write!(f, "deadline"),
+            TimeoutPhase::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+/// 超时设置，未设置的项不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// 收到响应头的超时时间，见TimeoutPhase::Headers
+    pub headers: Option<Duration>,
+    pub deadline: Option<Duration>,
+    pub idle: Option<Duration>,
+}
+
+impl Timeouts {
+    /// 请求级设置覆盖客户端设置
+    pub(crate) fn or(self, fallback: Timeouts) -> Timeouts {
+        Timeouts {
+            headers: self.headers.or(fallback.headers),
+            deadline: self.deadline.or(fallback.deadline),
+            idle: self.idle.or(fallback.idle),
+        }
+    }
+
+    /// 从started开始计算整个请求的截止时间
+    pub(crate) fn deadline_from(&self, started: Instant) -> Option<Instant> {
+        self.deadline.map(|deadline| started + deadline)
+    }
+}
+
+/// 以最先到期的限制等待future完成
+pub(crate) async fn with_timeout<F: Future>(future: F, limit: Option<Duration>, deadline: Option<Instant>, phase: TimeoutPhase) -> Result<F::Output> {
+    let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    let (duration, phase) = match (limit, remaining) {
+        (Some(limit), Some(remaining)) if remaining < limit => (remaining, TimeoutPhase::Deadline),
+        (Some(limit), _) => (limit, phase),
+        (None, Some(remaining)) => (remaining, TimeoutPhase::Deadline),
+        (None, None) => return Ok(future.await),
+    };
+
+    tokio::time::timeout(duration, future).await.map_err(|_| Error::Timeout { phase })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_timeout_reports_phase() {
+        let pending = std::future::pending::<()>;
+
+        let err = with_timeout(pending(), Some(Duration::from_millis(10)), None, TimeoutPhase::Idle).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { phase: TimeoutPhase::Idle }));
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let err = with_timeout(pending(), Some(Duration::from_secs(10)), Some(deadline), TimeoutPhase::Headers).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { phase: TimeoutPhase::Deadline }));
+
+        assert_eq!(with_timeout(async { 1 }, None, None, TimeoutPhase::Headers).await.unwrap(), 1);
+    }
+}
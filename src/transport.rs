@@ -0,0 +1,232 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use bytes::Bytes;
+use reqwest::{header::{HeaderMap, HeaderValue, CONTENT_TYPE}, StatusCode};
+
+use crate::error::{Error, Result};
+use crate::http::{HttpRequest, HttpResponse, ResponseBody};
+use crate::send::BoxFuture;
+use crate::timeout::TimeoutPhase;
+
+/// 实际发送HTTP请求的底层实现，位于中间件链的最内层
+pub trait HttpTransport: Send + Sync {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>>;
+}
+
+/// 默认实现，复用同一个reqwest::Client的连接池
+#[derive(Default, Clone)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用自定义的reqwest::Client，如设置代理或TLS
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self {
+            client,
+        }
+    }
+
+    /// 建立连接（包括TLS握手）的超时时间，超时时返回TimeoutPhase::Connect
+    pub fn with_connect_timeout(timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .build()
+            .expect("failed to build reqwest client");
+        Self::with_client(client)
+    }
+}
+
+struct ReqwestBody(reqwest::Response);
+
+impl ResponseBody for ReqwestBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Bytes>>> {
+        Box::pin(async move { Ok(self.0.chunk().await?) })
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        Box::pin(async move {
+            let response = self.client
+                .request(request.method, request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .send()
+                .await
+                .map_err(|e| match e.is_connect() && e.is_timeout() {
+                    true => Error::Timeout { phase: TimeoutPhase::Connect },
+                    false => Error::Reqwest(e),
+                })?;
+
+            Ok(HttpResponse {
+                status: response.status(),
+                headers: response.headers().clone(),
+                body: Box::new(ReqwestBody(response)),
+            })
+        })
+    }
+}
+
+/// MockTransport按顺序返回的一个响应
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    chunks: Vec<Bytes>,
+    chunk_delay: Option<Duration>,
+}
+
+impl MockResponse {
+    pub fn new(status: StatusCode, body: impl Into<Bytes>) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            chunks: vec![body.into()],
+            chunk_delay: None,
+        }
+    }
+
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self::new(StatusCode::OK, value.to_string())
+            .with_header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+    }
+
+    /// SSE响应，每个事件作为单独的一块返回，最后追加[DONE]
+    pub fn sse<I>(events: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let mut chunks: Vec<Bytes> = events.into_iter()
+            .map(|event| Bytes::from(format!("data: {}\n\n", event.to_string())))
+            .collect();
+        chunks.push(Bytes::from_static(b"data: [DONE]\n\n"));
+
+        Self::chunked(StatusCode::OK, chunks)
+            .with_header(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"))
+    }
+
+    /// 按原样逐块返回，可用于模拟被截断或跨块分割的数据
+    pub fn chunked<I>(status: StatusCode, chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            chunk_delay: None,
+        }
+    }
+
+    pub fn with_header(self, name: reqwest::header::HeaderName, value: HeaderValue) -> Self {
+        let mut headers = self.headers;
+        headers.insert(name, value);
+
+        Self {
+            headers,
+            ..self
+        }
+    }
+
+    /// 每一块返回前等待的时间
+    pub fn with_chunk_delay(self, delay: Duration) -> Self {
+        Self {
+            chunk_delay: Some(delay),
+            ..self
+        }
+    }
+}
+
+pub(crate) struct MockBody {
+    chunks: VecDeque<Bytes>,
+    delay: Option<Duration>,
+}
+
+impl MockBody {
+    pub(crate) fn new(chunks: Vec<Bytes>, delay: Option<Duration>) -> Self {
+        Self {
+            chunks: chunks.into(),
+            delay,
+        }
+    }
+}
+
+impl ResponseBody for MockBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Bytes>>> {
+        Box::pin(async move {
+            if self.chunks.is_empty() {
+                return Ok(None);
+            }
+            if let Some(delay) = self.delay {
+                tokio::time::sleep(delay).await;
+            }
+            Ok(self.chunks.pop_front())
+        })
+    }
+}
+
+/// 不访问网络的测试用实现：按顺序返回预设的响应，并记录收到的请求
+#[derive(Default)]
+pub struct MockTransport {
+    responses: Mutex<VecDeque<MockResponse>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_response(self, response: MockResponse) -> Self {
+        self.push(response);
+        self
+    }
+
+    pub fn push(&self, response: MockResponse) {
+        self.responses.lock().unwrap().push_back(response);
+    }
+
+    /// 已收到的全部请求
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// 尚未被使用的预设响应数
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+}
+
+impl HttpTransport for MockTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        let response = self.responses.lock().unwrap().pop_front();
+        let url = request.url.clone();
+        self.requests.lock().unwrap().push(request);
+
+        Box::pin(async move {
+            let Some(response) = response else {
+                return Err(Error::Transport(format!("no mock response left for {}", url)));
+            };
+
+            Ok(HttpResponse {
+                status: response.status,
+                headers: response.headers,
+                body: Box::new(MockBody::new(response.chunks, response.chunk_delay)),
+            })
+        })
+    }
+}
+
+// 让Arc包裹的transport可以直接传给客户端，便于测试中保留引用查看请求
+impl <T: HttpTransport + ?Sized> HttpTransport for std::sync::Arc<T> {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse>> {
+        (**self).send(request)
+    }
+}
@@ -0,0 +1,300 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}};
+
+use crate::authen::parse_api_key;
+use crate::chat::completions::request_inner::RequestInner;
+use crate::chat::completions::result::Usage;
+use crate::chat::context::estimate_tokens;
+use crate::error::{Error, Result};
+use crate::key_pool::KeyLease;
+use crate::model::Model;
+use crate::openglm::OpenGLM;
+
+/// 每百万token的价格，单位由调用方决定（如元）
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// 模型价格表，未配置价格的模型费用按0计算
+#[derive(Debug, Clone, Default)]
+pub struct PriceTable {
+    prices: HashMap<Model, Price>,
+}
+
+impl PriceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_price(mut self, model: impl Into<Model>, input: f64, output: f64) -> Self {
+        self.prices.insert(model.into(), Price { input, output });
+        self
+    }
+
+    pub fn price(&self, model: &Model) -> Price {
+        self.prices.get(model).copied().unwrap_or_default()
+    }
+
+    fn cost(&self, model: &Model, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        let price = self.price(model);
+        (prompt_tokens as f64 * price.input + completion_tokens as f64 * price.output) / 1_000_000.0
+    }
+}
+
+/// 累计用量
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, prompt_tokens: u64, completion_tokens: u64, cost: f64) {
+        self.requests += 1;
+        self.prompt_tokens += prompt_tokens;
+        self.completion_tokens += completion_tokens;
+        self.cost += cost;
+    }
+}
+
+/// 按模型、密钥id和调用方标签汇总的用量
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsageReport {
+    pub total: UsageTotals,
+    pub by_model: BTreeMap<String, UsageTotals>,
+    pub by_key: BTreeMap<String, UsageTotals>,
+    pub by_tag: BTreeMap<String, UsageTotals>,
+}
+
+/// 费用预算。硬预算超出后拒绝发送请求，软预算只通过回调告警
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    limit: f64,
+    hard: bool,
+    tag: Option<String>,
+}
+
+impl Budget {
+    pub fn hard(limit: f64) -> Self {
+        Self { limit, hard: true, tag: None }
+    }
+
+    pub fn soft(limit: f64) -> Self {
+        Self { limit, hard: false, tag: None }
+    }
+
+    /// 只统计带有该标签的请求，默认统计全部请求
+    pub fn for_tag(self, tag: String) -> Self {
+        Self {
+            tag: Some(tag),
+            ..self
+        }
+    }
+}
+
+/// 软预算告警，projected为已花费加上本次请求输入部分的估算费用
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetWarning {
+    pub tag: Option<String>,
+    pub spent: f64,
+    pub projected: f64,
+    pub limit: f64,
+}
+
+type WarningHandler = Box<dyn Fn(&BudgetWarning) + Send + Sync>;
+
+/// 用量统计：汇总每次请求（包括流式请求最后一个片段）返回的usage，按价格表计算费用，并在发送前检查预算。
+pub struct UsageTracker {
+    prices: PriceTable,
+    budgets: Vec<Budget>,
+    on_warning: Option<WarningHandler>,
+    report: Mutex<UsageReport>,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self {
+            prices: PriceTable::new(),
+            budgets: Vec::new(),
+            on_warning: None,
+            report: Mutex::new(UsageReport::default()),
+        }
+    }
+
+    pub fn with_prices(self, prices: PriceTable) -> Self {
+        Self {
+            prices,
+            ..self
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budgets.push(budget);
+        self
+    }
+
+    /// 超出软预算时调用，默认不做处理；开启tracing特性时另外记录一条WARN级别的事件
+    pub fn on_budget_warning(self, on_warning: impl Fn(&BudgetWarning) + Send + Sync + 'static) -> Self {
+        Self {
+            on_warning: Some(Box::new(on_warning)),
+            ..self
+        }
+    }
+
+    pub fn report(&self) -> UsageReport {
+        self.report.lock().unwrap().clone()
+    }
+
+    /// 清空已累计的用量，如按天重置预算
+    pub fn reset(&self) {
+        *self.report.lock().unwrap() = UsageReport::default();
+    }
+
+    fn spent(&self, tag: Option<&str>) -> f64 {
+        let report = self.report.lock().unwrap();
+        match tag {
+            Some(tag) => report.by_tag.get(tag).map_or(0.0, |totals| totals.cost),
+            None => report.total.cost,
+        }
+    }
+
+    fn check_budgets(&self, model: &Model, tag: Option<&str>, prompt_tokens: u64) -> Result<()> {
+        let estimated = self.prices.cost(model, prompt_tokens, 0);
+        for budget in self.budgets.iter().filter(|budget| budget.tag.is_none() || budget.tag.as_deref() == tag) {
+            let spent = self.spent(budget.tag.as_deref());
+            let projected = spent + estimated;
+            if projected < budget.limit {
+                continue;
+            }
+
+            if budget.hard {
+                return Err(Error::BudgetExceeded { spent, limit: budget.limit });
+            }
+            let warning = BudgetWarning { tag: budget.tag.clone(), spent, projected, limit: budget.limit };
+            #[cfg(feature = "tracing")]
+            tracing::warn!(tag = warning.tag.as_deref(), spent, projected, limit = warning.limit, "soft budget exceeded");
+            if let Some(on_warning) = &self.on_warning {
+                on_warning(&warning);
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&self, model: &Model, key: &str, tag: Option<&str>, usage: &Usage) {
+        let prompt_tokens = usage.prompt_tokens.max(0) as u64;
+        let completion_tokens = usage.completion_tokens.max(0) as u64;
+        let cost = self.prices.cost(model, prompt_tokens, completion_tokens);
+
+        let mut report = self.report.lock().unwrap();
+        report.total.add(prompt_tokens, completion_tokens, cost);
+        report.by_model.entry(model.to_string()).or_default().add(prompt_tokens, completion_tokens, cost);
+        report.by_key.entry(key.to_string()).or_default().add(prompt_tokens, completion_tokens, cost);
+        if let Some(tag) = tag {
+            report.by_tag.entry(tag.to_string()).or_default().add(prompt_tokens, completion_tokens, cost);
+        }
+    }
+}
+
+/// 一次请求的统计维度，发送前检查预算，收到usage后记录
+pub(crate) struct UsageScope {
+    tracker: Arc<UsageTracker>,
+    model: Model,
+    key: String,
+    tag: Option<String>,
+}
+
+impl UsageScope {
+    pub(crate) fn new(client: &OpenGLM, inner: &RequestInner) -> Option<Self> {
+        let tracker = client.usage_tracker_arc()?;
+        let model = inner.model()?.clone();
+        let key = parse_api_key(client.api_key()).map_or(String::new(), |(id, _)| id.to_string());
+        Some(Self { tracker, model, key, tag: inner.usage_tag().map(str::to_string) })
+    }
+
+    /// 使用密钥池时按实际选中的密钥统计
+    pub(crate) fn with_lease(self, lease: Option<&KeyLease>) -> Self {
+        match lease {
+            Some(lease) => Self {
+                key: lease.id().to_string(),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    pub(crate) fn check_budgets(&self, inner: &RequestInner) -> Result<()> {
+        let prompt = serde_json::to_string(&inner.to_body(false)?["messages"])?;
+        self.tracker.check_budgets(&self.model, self.tag.as_deref(), estimate_tokens(&prompt) as u64)
+    }
+
+    pub(crate) fn record(&self, usage: &Usage) {
+        self.tracker.record(&self.model, &self.key, self.tag.as_deref(), usage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn test_usage_tracker() {
+        let completion = MockResponse::json(&serde_json::json!({
+            "id": "1", "created": 1711433468, "model": "glm-4",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "你好"}}],
+            "usage": {"prompt_tokens": 600_000, "completion_tokens": 100_000, "total_tokens": 700_000},
+        }));
+        // 每个片段都带有累计的usage，只应记录最后一次
+        let chunk = |content: &str, completion_tokens: i64| serde_json::json!({
+            "id": "2", "created": 1711433468, "model": "glm-4-flash",
+            "choices": [{"index": 0, "delta": {"role": "assistant", "content": content}}],
+            "usage": {"prompt_tokens": 10, "completion_tokens": completion_tokens, "total_tokens": 10 + completion_tokens},
+        });
+        let chunk = MockResponse::sse([chunk("你", 1), chunk("好", 2)]);
+        let transport = MockTransport::new().with_response(chunk).with_response(completion);
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let tracker = UsageTracker::new()
+            .with_prices(PriceTable::new().with_price(Model::Glm4, 100.0, 200.0))
+            .with_budget(Budget::soft(50.0))
+            .with_budget(Budget::hard(80.0).for_tag("eval".to_string()))
+            .on_budget_warning({
+                let warnings = warnings.clone();
+                move |warning| warnings.lock().unwrap().push(warning.clone())
+            });
+        let client = OpenGLM::new("mockid.mocksecret".to_string())
+            .with_transport(transport)
+            .with_usage_tracker(tracker);
+        let request = |model: Model| client.chat().completions().create()
+            .with_model(model)
+            .with_usage_tag("eval".to_string())
+            .add_message(ChatMessage::User("你好".to_string()));
+
+        let mut iter = request(Model::Glm4Flash).stream().send().await.unwrap();
+        while iter.next().await.unwrap().is_some() {}
+        request(Model::Glm4).send().await.unwrap();
+
+        let report = client.usage_tracker().unwrap().report();
+        assert_eq!(report.total.requests, 2);
+        assert_eq!(report.total.prompt_tokens, 600_010);
+        assert!((report.by_model["glm-4"].cost - 80.0).abs() < 1e-9);
+        assert_eq!(report.by_model["glm-4-flash"].cost, 0.0);
+        assert_eq!(report.by_key["mockid"].requests, 2);
+        assert_eq!(report.by_tag["eval"].completion_tokens, 100_002);
+        assert!(warnings.lock().unwrap().is_empty());
+
+        // 软预算只告警，硬预算拒绝发送
+        let err = request(Model::Glm4).send().await.unwrap_err();
+        assert!(matches!(err, Error::BudgetExceeded { limit, .. } if limit == 80.0));
+        assert_eq!(warnings.lock().unwrap().len(), 1);
+    }
+}